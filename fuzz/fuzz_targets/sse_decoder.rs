@@ -0,0 +1,42 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use poe_api_process::sse::SseDecoder;
+use poe_api_process::{ChatResponse, ChatResponseData};
+
+// 以首位元組決定切分大小，同時檢查任意輸入不 panic 且結果與切分方式無關
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk_size, body)) = data.split_first() else {
+        return;
+    };
+    let chunk_size = usize::from(chunk_size).max(1);
+
+    // 輸入遠小於單行上限，push 不會返回錯誤
+    let mut whole = SseDecoder::new();
+    let mut expected = whole.push(body).unwrap();
+    expected.extend(whole.finish());
+
+    let mut chunked = SseDecoder::new();
+    let mut actual = Vec::new();
+    for chunk in body.chunks(chunk_size) {
+        actual.extend(chunked.push(chunk).unwrap());
+    }
+    actual.extend(chunked.finish());
+
+    assert_eq!(normalize(expected), normalize(actual));
+});
+
+// XML 工具調用的 ID 由全局計數器產生，比較前清空，其餘內容需完全一致
+fn normalize(events: Vec<ChatResponse>) -> Vec<String> {
+    events
+        .into_iter()
+        .map(|mut event| {
+            if let Some(ChatResponseData::ToolCalls(tool_calls)) = &mut event.data {
+                for tool_call in tool_calls {
+                    tool_call.id.clear();
+                }
+            }
+            format!("{:?}", event)
+        })
+        .collect()
+}
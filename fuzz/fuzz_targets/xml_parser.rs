@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use poe_api_process::xml::XmlToolCallParser;
+use poe_api_process::{ChatMessage, ChatTool, FunctionDefinition, PoeClient, Role};
+
+fuzz_target!(|text: &str| {
+    let tools = vec![ChatTool {
+        r#type: "function".to_string(),
+        function: FunctionDefinition {
+            name: "get_weather".to_string(),
+            description: None,
+            parameters: None,
+        },
+    }];
+
+    XmlToolCallParser::parse_xml_tool_calls(text);
+    XmlToolCallParser::parse_xml_tool_calls_with_tools(text, &tools);
+
+    let message = ChatMessage::new(Role::Bot, text);
+    message.contains_xml_tool_calls_with_tools(&tools);
+    message.extract_xml_tool_calls_with_tools(&tools);
+    PoeClient::remove_xml_tool_calls(text);
+});
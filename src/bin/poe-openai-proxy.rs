@@ -0,0 +1,58 @@
+use poe_api_process::proxy::{DEFAULT_POE_BASE_URL, DEFAULT_POE_FILE_UPLOAD_URL, OpenAiProxy};
+use std::env;
+use std::process::ExitCode;
+
+// 預設監聽地址
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:8080";
+
+const USAGE: &str = "用法: poe-openai-proxy [監聽地址]
+
+環境變數:
+  POE_ACCESS_KEY       Poe API 訪問密鑰（未設置時使用請求中的 Bearer token）
+  POE_PROXY_KEY        調用方須以 Bearer token 攜帶的代理密鑰
+  POE_BASE_URL         Poe API 地址（預設 https://api.poe.com）
+  POE_FILE_UPLOAD_URL  檔案上傳地址
+  POE_PROXY_ADDR       監聽地址（預設 127.0.0.1:8080）
+
+信任模型:
+  未設置 POE_ACCESS_KEY 時，調用方以自己的 Poe 密鑰作為 Bearer token，代理不保存任何密鑰。
+  設置 POE_ACCESS_KEY 後，所有請求都以該密鑰消耗點數：
+  設置 POE_PROXY_KEY 時調用方必須攜帶該代理密鑰；
+  未設置 POE_PROXY_KEY 時任何能連線的人都能使用該密鑰，因此只允許監聽本機回環地址。";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let addr_arg = args.next();
+    if matches!(addr_arg.as_deref(), Some("-h" | "--help")) {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let addr = addr_arg
+        .or_else(|| env::var("POE_PROXY_ADDR").ok())
+        .unwrap_or_else(|| DEFAULT_LISTEN_ADDR.to_string());
+    let access_key = env::var("POE_ACCESS_KEY")
+        .ok()
+        .filter(|key| !key.is_empty());
+    let proxy_key = env::var("POE_PROXY_KEY").ok().filter(|key| !key.is_empty());
+    let base_url = env::var("POE_BASE_URL").unwrap_or_else(|_| DEFAULT_POE_BASE_URL.to_string());
+    let upload_url =
+        env::var("POE_FILE_UPLOAD_URL").unwrap_or_else(|_| DEFAULT_POE_FILE_UPLOAD_URL.to_string());
+
+    let mut proxy = OpenAiProxy::new(access_key.as_deref())
+        .with_base_url(&base_url)
+        .with_file_upload_url(&upload_url);
+    if let Some(proxy_key) = proxy_key {
+        proxy = proxy.with_proxy_key(&proxy_key);
+    }
+
+    eprintln!("OpenAI 相容代理監聽於 http://{}", addr);
+    match proxy.serve(addr.as_str()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("代理伺服器錯誤: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use clap::{Args, Parser, Subcommand};
+use poe_api_process::cli::{
+    ChatSession, CliConfig, ModelFilter, OutputFormat, UploadRecord, load_conversation,
+    render_models, render_uploads, save_conversation, upload_request,
+};
+use poe_api_process::conversation::Conversation;
+use poe_api_process::upload::{DEFAULT_BATCH_CONCURRENCY, UploadOptions};
+use poe_api_process::{ChatEventType, ChatMessage, ChatResponse, ChatResponseData, PoeError};
+use std::io::{IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const REPL_HELP: &str = "可用指令:
+  /attach <檔案>  為下一條消息附加檔案
+  /save <檔案>    保存目前的對話
+  /reset          清空對話記錄
+  /help           顯示此說明
+  /exit, /quit    離開";
+
+/// Poe API 命令列工具
+///
+/// 配置依序讀取自配置文件（--config、$POE_CONFIG 或 ~/.config/poe/config.toml）
+/// 及環境變數 POE_ACCESS_KEY、POE_BOT、POE_BASE_URL、POE_FILE_UPLOAD_URL。
+#[derive(Parser)]
+#[command(name = "poe", version)]
+struct Cli {
+    /// 配置文件路徑
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// 使用的 bot，覆蓋配置中的設定
+    #[arg(long, short, global = true)]
+    bot: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 互動式對話（未指定子命令時的預設行為）
+    Chat(ChatArgs),
+    /// 單次提問，未提供問題時從標準輸入讀取
+    Ask {
+        #[command(flatten)]
+        chat: ChatArgs,
+        /// 問題內容
+        prompt: Vec<String>,
+    },
+    /// 列出可用模型
+    Models {
+        /// 模型描述的語言代碼（僅適用於傳統模型列表 API）
+        #[arg(long)]
+        lang: Option<String>,
+
+        /// 使用需要 access key 的 v1/models API
+        #[arg(long)]
+        v1: bool,
+
+        /// 按擁有者過濾，支援 * 及 ? 通配符
+        #[arg(long)]
+        owner: Option<String>,
+
+        /// 按模型名稱過濾，支援 * 及 ? 通配符
+        #[arg(long)]
+        name: Option<String>,
+
+        /// 輸出格式
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// 批量上傳本地檔案或 http(s) 地址
+    Upload {
+        /// 要上傳的檔案路徑或地址
+        #[arg(required = true)]
+        sources: Vec<String>,
+
+        /// 本地檔案的 MIME 類型
+        #[arg(long)]
+        mime_type: Option<String>,
+
+        /// 同時上傳的最大檔案數
+        #[arg(long, default_value_t = DEFAULT_BATCH_CONCURRENCY)]
+        concurrency: usize,
+
+        /// 每個檔案失敗後的重試次數
+        #[arg(long, default_value_t = 0)]
+        retries: u32,
+
+        /// 輸出格式
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Args, Default)]
+struct ChatArgs {
+    /// 附加本地檔案，可重複指定
+    #[arg(long, short)]
+    attach: Vec<PathBuf>,
+
+    /// 以 JSON 行輸出每個事件
+    #[arg(long)]
+    json: bool,
+
+    /// 從文件恢復對話（.json 或 .jsonl）
+    #[arg(long)]
+    resume: Option<PathBuf>,
+
+    /// 將對話保存到文件（.json 或 .jsonl）
+    #[arg(long)]
+    save: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("錯誤: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), PoeError> {
+    let mut config = CliConfig::load(cli.config.as_deref()).await?;
+    if let Some(bot) = cli.bot.clone() {
+        config.bot = Some(bot);
+    }
+
+    match cli.command {
+        Some(Command::Ask { chat, prompt }) => ask(&config, cli.bot, chat, prompt).await,
+        Some(Command::Chat(chat)) => repl(&config, cli.bot, chat).await,
+        Some(Command::Models {
+            lang,
+            v1,
+            owner,
+            name,
+            format,
+        }) => {
+            let models = config.model_list(lang.as_deref(), v1).await?;
+            let models = ModelFilter { owner, name }.apply(models);
+            println!("{}", render_models(&models, format)?);
+            Ok(())
+        }
+        Some(Command::Upload {
+            sources,
+            mime_type,
+            concurrency,
+            retries,
+            format,
+        }) => {
+            let options = UploadOptions::new()
+                .with_concurrency(concurrency)
+                .with_retries(retries);
+            upload(&config, sources, mime_type, &options, format).await
+        }
+        // 標準輸入為管道時直接提問，否則進入互動模式
+        None if !std::io::stdin().is_terminal() => {
+            ask(&config, cli.bot, ChatArgs::default(), Vec::new()).await
+        }
+        None => repl(&config, cli.bot, ChatArgs::default()).await,
+    }
+}
+
+// 建立會話，恢復對話時沿用記錄中的 bot（命令列參數優先）
+async fn open_session(
+    config: &CliConfig,
+    bot_arg: Option<String>,
+    args: &ChatArgs,
+) -> Result<ChatSession, PoeError> {
+    let mut conversation = match &args.resume {
+        Some(path) => load_conversation(path).await?,
+        None => Conversation::default(),
+    };
+    let bot_name = bot_arg
+        .or_else(|| conversation.bot_name.clone())
+        .unwrap_or_else(|| config.bot_name().to_string());
+    conversation.bot_name = Some(bot_name.clone());
+    Ok(ChatSession::new(config.client(&bot_name)?, conversation))
+}
+
+async fn ask(
+    config: &CliConfig,
+    bot_arg: Option<String>,
+    args: ChatArgs,
+    prompt: Vec<String>,
+) -> Result<(), PoeError> {
+    let mut prompt = prompt.join(" ");
+    if prompt.is_empty() || prompt == "-" {
+        prompt.clear();
+        std::io::stdin().read_to_string(&mut prompt)?;
+    }
+    if prompt.trim().is_empty() {
+        return Err(PoeError::InvalidRequest("問題內容不能為空".to_string()));
+    }
+
+    let mut session = open_session(config, bot_arg, &args).await?;
+    let message =
+        with_attachments(&session, ChatMessage::user(prompt.trim()), &args.attach).await?;
+    let json = args.json;
+    session
+        .send(message, |response| print_event(response, json))
+        .await?;
+
+    if let Some(path) = &args.save {
+        save_conversation(session.conversation(), path).await?;
+    }
+    Ok(())
+}
+
+async fn repl(config: &CliConfig, bot_arg: Option<String>, args: ChatArgs) -> Result<(), PoeError> {
+    let mut session = open_session(config, bot_arg, &args).await?;
+    let mut pending_attachments = args.attach.clone();
+    let bot_name = session.conversation().bot_name.clone().unwrap_or_default();
+    eprintln!("與 {} 對話中，輸入 /help 查看指令", bot_name);
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        eprint!("> ");
+        std::io::stderr().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            break;
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(command) = line.strip_prefix('/') {
+            let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+            let argument = argument.trim();
+            match name {
+                "exit" | "quit" => break,
+                "help" => eprintln!("{}", REPL_HELP),
+                "reset" => {
+                    session.reset();
+                    eprintln!("已清空對話記錄");
+                }
+                "attach" if !argument.is_empty() => {
+                    pending_attachments.push(PathBuf::from(argument));
+                    eprintln!("已加入附件: {}", argument);
+                }
+                "save" if !argument.is_empty() => {
+                    match save_conversation(session.conversation(), argument.as_ref()).await {
+                        Ok(()) => eprintln!("對話已保存至 {}", argument),
+                        Err(e) => eprintln!("保存失敗: {}", e),
+                    }
+                }
+                _ => eprintln!("未知指令: /{}\n{}", name, REPL_HELP),
+            }
+            continue;
+        }
+
+        let message =
+            match with_attachments(&session, ChatMessage::user(line), &pending_attachments).await {
+                Ok(message) => message,
+                Err(e) => {
+                    eprintln!("附件上傳失敗: {}", e);
+                    continue;
+                }
+            };
+        pending_attachments.clear();
+
+        let json = args.json;
+        if let Err(e) = session
+            .send(message, |response| print_event(response, json))
+            .await
+        {
+            eprintln!("錯誤: {}", e);
+        }
+    }
+
+    if let Some(path) = &args.save {
+        save_conversation(session.conversation(), path).await?;
+        eprintln!("對話已保存至 {}", path.display());
+    }
+    Ok(())
+}
+
+async fn upload(
+    config: &CliConfig,
+    sources: Vec<String>,
+    mime_type: Option<String>,
+    options: &UploadOptions,
+    format: OutputFormat,
+) -> Result<(), PoeError> {
+    let requests = sources
+        .iter()
+        .map(|source| upload_request(source, mime_type.as_deref()))
+        .collect();
+    let items = config
+        .client(config.bot_name())?
+        .upload_files_batch_with(requests, options)
+        .await;
+
+    // 成功的項目照常輸出，失敗的項目寫到標準錯誤
+    let mut records = Vec::new();
+    let mut failures = 0;
+    for (source, item) in sources.into_iter().zip(items) {
+        match item.result {
+            Ok(response) => records.push(UploadRecord { source, response }),
+            Err(e) => {
+                eprintln!("上傳失敗: {}: {}", source, e);
+                failures += 1;
+            }
+        }
+    }
+    println!("{}", render_uploads(&records, format)?);
+
+    if failures > 0 {
+        return Err(PoeError::FileUploadFailed(format!(
+            "{} 個檔案上傳失敗",
+            failures
+        )));
+    }
+    Ok(())
+}
+
+async fn with_attachments(
+    session: &ChatSession,
+    mut message: ChatMessage,
+    paths: &[PathBuf],
+) -> Result<ChatMessage, PoeError> {
+    for attachment in session.upload_attachments(paths).await? {
+        message = message.with_attachment(attachment);
+    }
+    Ok(message)
+}
+
+// 輸出單個事件，文本即時寫出以保持串流效果
+fn print_event(response: &ChatResponse, json: bool) {
+    if json {
+        if let Ok(line) = serde_json::to_string(response) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    let mut stdout = std::io::stdout();
+    match (&response.event, &response.data) {
+        (ChatEventType::Text, Some(ChatResponseData::Text { text })) => {
+            print!("{}", text);
+        }
+        (ChatEventType::ReplaceResponse, Some(ChatResponseData::Text { text })) => {
+            print!("\n{}", text);
+        }
+        (ChatEventType::SuggestedReply, Some(ChatResponseData::Text { text })) => {
+            eprintln!("[建議回覆] {}", text);
+        }
+        (_, Some(ChatResponseData::File(file))) => {
+            println!("\n[檔案] {} {}", file.name, file.url);
+        }
+        (ChatEventType::Done, _) => println!(),
+        _ => {}
+    }
+    let _ = stdout.flush();
+}
@@ -0,0 +1,539 @@
+use crate::client::PoeClient;
+use crate::error::PoeError;
+use crate::testing::{MOCK_GQL_PATH, MOCK_UPLOAD_PATH};
+use axum::Router;
+use axum::body::{Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+#[cfg(feature = "trace")]
+use tracing::{debug, warn};
+
+// 卡帶格式版本
+pub const CASSETTE_VERSION: u32 = 1;
+// 替換敏感資訊的佔位符
+pub const REDACTED: &str = "[REDACTED]";
+
+// 記錄時保留的請求標頭
+const RECORDED_REQUEST_HEADERS: &[&str] = &["authorization", "content-type", "cookie"];
+
+// 位元組內容，有效 UTF-8 以文本保存，其他以 base64 保存
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CassetteData {
+    Text { text: String },
+    Base64 { base64: String },
+}
+
+impl CassetteData {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => CassetteData::Text {
+                text: text.to_string(),
+            },
+            Err(_) => CassetteData::Base64 {
+                base64: BASE64.encode(bytes),
+            },
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Bytes, PoeError> {
+        match self {
+            CassetteData::Text { text } => Ok(Bytes::from(text.clone())),
+            CassetteData::Base64 { base64 } => BASE64
+                .decode(base64)
+                .map(Bytes::from)
+                .map_err(|e| PoeError::EventParseFailed(format!("卡帶 base64 解碼失敗: {}", e))),
+        }
+    }
+
+    // 將所有敏感字串替換為佔位符
+    fn redact(bytes: &[u8], secrets: &[String]) -> Self {
+        let mut data = Self::from_bytes(bytes);
+        if let CassetteData::Text { ref mut text } = data {
+            for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+                *text = text.replace(secret.as_str(), REDACTED);
+            }
+        }
+        data
+    }
+}
+
+// 記錄的請求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CassetteRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: CassetteData,
+}
+
+// 回應的單個原始位元組塊及其與上一塊的間隔
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CassetteChunk {
+    pub delay_ms: u64,
+    #[serde(flatten)]
+    pub data: CassetteData,
+}
+
+// 記錄的回應
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CassetteResponse {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub chunks: Vec<CassetteChunk>,
+}
+
+// 一次完整的 HTTP 往返
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: CassetteRequest,
+    pub response: CassetteResponse,
+}
+
+// 記錄的 HTTP 往返集合
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cassette {
+    pub version: u32,
+    pub interactions: Vec<Interaction>,
+}
+
+impl Default for Cassette {
+    fn default() -> Self {
+        Self {
+            version: CASSETTE_VERSION,
+            interactions: Vec::new(),
+        }
+    }
+}
+
+impl Cassette {
+    /// 儲存為 JSON 檔案
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), PoeError> {
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(path.as_ref(), json).await?;
+        Ok(())
+    }
+
+    /// 從 JSON 檔案載入
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, PoeError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Err(PoeError::FileNotFound(path.display().to_string()));
+        }
+        let text = tokio::fs::read_to_string(path).await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+// 上游地址
+#[derive(Debug, Clone)]
+struct Upstream {
+    base_url: String,
+    upload_url: String,
+    gql_url: String,
+}
+
+impl Upstream {
+    // 根據路徑選擇上游地址
+    fn url_for(&self, path_and_query: &str) -> String {
+        if path_and_query.starts_with(MOCK_UPLOAD_PATH) {
+            self.upload_url.clone()
+        } else if path_and_query.starts_with(MOCK_GQL_PATH) {
+            self.gql_url.clone()
+        } else {
+            format!("{}{}", self.base_url.trim_end_matches('/'), path_and_query)
+        }
+    }
+}
+
+struct RecorderState {
+    upstream: Upstream,
+    client: reqwest::Client,
+    secrets: Vec<String>,
+    cassette: Cassette,
+}
+
+// 錄製代理：轉發請求到真實上游並記錄原始回應塊及其時間間隔
+pub struct CassetteRecorder {
+    addr: SocketAddr,
+    state: Arc<Mutex<RecorderState>>,
+    handle: JoinHandle<()>,
+}
+
+impl CassetteRecorder {
+    /// 啟動錄製代理，bot 及 v1 請求轉發到 base_url，上傳及 GraphQL 請求轉發到對應地址
+    pub async fn start(base_url: &str, upload_url: &str, gql_url: &str) -> Result<Self, PoeError> {
+        let state = Arc::new(Mutex::new(RecorderState {
+            upstream: Upstream {
+                base_url: base_url.to_string(),
+                upload_url: upload_url.to_string(),
+                gql_url: gql_url.to_string(),
+            },
+            client: reqwest::Client::new(),
+            secrets: Vec::new(),
+            cassette: Cassette::default(),
+        }));
+        let router = Router::new()
+            .fallback(handle_record)
+            .with_state(state.clone());
+        let (addr, handle) = spawn(router).await?;
+
+        #[cfg(feature = "trace")]
+        debug!("卡帶錄製代理已啟動: {} -> {}", addr, base_url);
+
+        Ok(Self {
+            addr,
+            state,
+            handle,
+        })
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn upload_url(&self) -> String {
+        format!("{}{}", self.base_url(), MOCK_UPLOAD_PATH)
+    }
+
+    pub fn gql_url(&self) -> String {
+        format!("{}{}", self.base_url(), MOCK_GQL_PATH)
+    }
+
+    /// 建立經由錄製代理的客戶端
+    pub fn client(&self, bot_name: &str, access_key: &str) -> PoeClient {
+        PoeClient::new(bot_name, access_key, &self.base_url(), &self.upload_url())
+    }
+
+    /// 額外需要從請求內容中抹除的敏感字串（Authorization 標頭總是會被抹除）
+    pub fn redact(&self, secret: &str) -> &Self {
+        self.lock().secrets.push(secret.to_string());
+        self
+    }
+
+    /// 目前已記錄的卡帶
+    pub fn cassette(&self) -> Cassette {
+        self.lock().cassette.clone()
+    }
+
+    /// 停止錄製並返回卡帶
+    pub fn finish(self) -> Cassette {
+        self.handle.abort();
+        self.cassette()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RecorderState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for CassetteRecorder {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+// 抹除標頭中的敏感資訊
+fn redact_header(name: &str, value: &str, secrets: &[String]) -> String {
+    if name == "authorization" {
+        return match value.split_once(' ') {
+            Some((scheme, _)) => format!("{} {}", scheme, REDACTED),
+            None => REDACTED.to_string(),
+        };
+    }
+    let mut value = value.to_string();
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        value = value.replace(secret.as_str(), REDACTED);
+    }
+    value
+}
+
+async fn handle_record(
+    State(state): State<Arc<Mutex<RecorderState>>>,
+    request: Request,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| parts.uri.path().to_string());
+
+    // 記錄請求並取得互動的索引
+    let (client, url, index) = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut headers = BTreeMap::new();
+        for name in RECORDED_REQUEST_HEADERS {
+            if let Some(value) = parts.headers.get(*name).and_then(|v| v.to_str().ok()) {
+                headers.insert(name.to_string(), redact_header(name, value, &state.secrets));
+            }
+        }
+        let cassette_request = CassetteRequest {
+            method: parts.method.to_string(),
+            path: path.clone(),
+            headers,
+            body: CassetteData::redact(&body, &state.secrets),
+        };
+        state.cassette.interactions.push(Interaction {
+            request: cassette_request,
+            response: CassetteResponse {
+                status: 0,
+                content_type: None,
+                chunks: Vec::new(),
+            },
+        });
+        (
+            state.client.clone(),
+            state.upstream.url_for(&path),
+            state.cassette.interactions.len() - 1,
+        )
+    };
+
+    let mut upstream_request = client.request(parts.method.clone(), &url).body(body);
+    for (name, value) in parts.headers.iter() {
+        if name != header::HOST && name != header::CONTENT_LENGTH {
+            upstream_request = upstream_request.header(name, value);
+        }
+    }
+
+    let upstream_response = match upstream_request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            #[cfg(feature = "trace")]
+            warn!("錄製代理轉發請求失敗: {}", e);
+            return (StatusCode::BAD_GATEWAY, e.to_string()).into_response();
+        }
+    };
+
+    let status = upstream_response.status();
+    let content_type = upstream_response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let response = &mut state.cassette.interactions[index].response;
+        response.status = status.as_u16();
+        response.content_type = content_type.clone();
+    }
+
+    // 邊轉發邊記錄每個原始位元組塊
+    let mut last_chunk_at = Instant::now();
+    let record_state = state.clone();
+    let stream = upstream_response.bytes_stream().map(move |chunk| {
+        let now = Instant::now();
+        let delay_ms = now.duration_since(last_chunk_at).as_millis() as u64;
+        last_chunk_at = now;
+        if let Ok(ref bytes) = chunk {
+            let mut state = record_state.lock().unwrap_or_else(|e| e.into_inner());
+            state.cassette.interactions[index]
+                .response
+                .chunks
+                .push(CassetteChunk {
+                    delay_ms,
+                    data: CassetteData::from_bytes(bytes),
+                });
+        }
+        chunk
+    });
+
+    let mut response = Response::new(Body::from_stream(stream));
+    *response.status_mut() = status;
+    if let Some(content_type) = content_type.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type);
+    }
+    response
+}
+
+struct PlayerState {
+    cassette: Cassette,
+    used: Vec<bool>,
+    replay_delays: bool,
+}
+
+// 重播伺服器：按記錄的塊邊界及時間間隔重播卡帶中的回應
+pub struct CassettePlayer {
+    addr: SocketAddr,
+    state: Arc<Mutex<PlayerState>>,
+    handle: JoinHandle<()>,
+}
+
+impl CassettePlayer {
+    /// 啟動重播伺服器，預設不重播塊之間的延遲
+    pub async fn start(cassette: Cassette) -> Result<Self, PoeError> {
+        let used = vec![false; cassette.interactions.len()];
+        let state = Arc::new(Mutex::new(PlayerState {
+            cassette,
+            used,
+            replay_delays: false,
+        }));
+        let router = Router::new()
+            .fallback(handle_replay)
+            .with_state(state.clone());
+        let (addr, handle) = spawn(router).await?;
+
+        #[cfg(feature = "trace")]
+        debug!("卡帶重播伺服器已啟動: {}", addr);
+
+        Ok(Self {
+            addr,
+            state,
+            handle,
+        })
+    }
+
+    /// 從檔案載入卡帶並啟動重播伺服器
+    pub async fn from_file(path: impl AsRef<Path>) -> Result<Self, PoeError> {
+        Self::start(Cassette::load(path).await?).await
+    }
+
+    /// 是否按記錄的時間間隔重播
+    pub fn replay_delays(&self, replay_delays: bool) -> &Self {
+        self.lock().replay_delays = replay_delays;
+        self
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn upload_url(&self) -> String {
+        format!("{}{}", self.base_url(), MOCK_UPLOAD_PATH)
+    }
+
+    pub fn gql_url(&self) -> String {
+        format!("{}{}", self.base_url(), MOCK_GQL_PATH)
+    }
+
+    /// 建立指向重播伺服器的客戶端
+    pub fn client(&self, bot_name: &str) -> PoeClient {
+        PoeClient::new(bot_name, "test-key", &self.base_url(), &self.upload_url())
+    }
+
+    /// 尚未被重播的互動數量
+    pub fn remaining(&self) -> usize {
+        self.lock().used.iter().filter(|used| !**used).count()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PlayerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for CassettePlayer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn handle_replay(State(state): State<Arc<Mutex<PlayerState>>>, request: Request) -> Response {
+    let method = request.method().to_string();
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+
+    // 按記錄順序取出第一個未使用且方法及路徑相同的互動
+    let (interaction, replay_delays) = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let found =
+            state
+                .cassette
+                .interactions
+                .iter()
+                .enumerate()
+                .position(|(index, interaction)| {
+                    !state.used[index]
+                        && interaction.request.method == method
+                        && interaction.request.path == path
+                });
+        match found {
+            Some(index) => {
+                state.used[index] = true;
+                (
+                    state.cassette.interactions[index].clone(),
+                    state.replay_delays,
+                )
+            }
+            None => {
+                #[cfg(feature = "trace")]
+                warn!("卡帶中找不到匹配的請求: {} {}", method, path);
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!("卡帶中找不到匹配的請求: {} {}", method, path),
+                )
+                    .into_response();
+            }
+        }
+    };
+
+    let chunks = match interaction
+        .response
+        .chunks
+        .iter()
+        .map(|chunk| Ok((chunk.delay_ms, chunk.data.to_bytes()?)))
+        .collect::<Result<Vec<_>, PoeError>>()
+    {
+        Ok(chunks) => chunks,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let stream = futures_util::stream::iter(chunks).then(move |(delay_ms, bytes)| async move {
+        if replay_delays && delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        }
+        Ok::<Bytes, Infallible>(bytes)
+    });
+
+    let mut response = Response::new(Body::from_stream(stream));
+    *response.status_mut() =
+        StatusCode::from_u16(interaction.response.status).unwrap_or(StatusCode::OK);
+    if let Some(content_type) = interaction
+        .response
+        .content_type
+        .as_deref()
+        .and_then(|value| HeaderValue::from_str(value).ok())
+    {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type);
+    }
+    response
+}
+
+// 在 127.0.0.1 的隨機端口啟動路由
+async fn spawn(router: Router) -> Result<(SocketAddr, JoinHandle<()>), PoeError> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| PoeError::ServerError(format!("無法綁定地址: {}", e)))?;
+    let addr = listener
+        .local_addr()
+        .map_err(|e| PoeError::ServerError(e.to_string()))?;
+    let handle = tokio::spawn(async move {
+        let _ = axum::serve(listener, router).await;
+    });
+    Ok((addr, handle))
+}
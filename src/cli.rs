@@ -0,0 +1,427 @@
+use crate::client::{
+    DEFAULT_POE_BASE_URL, DEFAULT_POE_FILE_UPLOAD_URL, DEFAULT_POE_GQL_URL, PoeClient,
+    get_model_list_from,
+};
+use crate::conversation::Conversation;
+use crate::error::PoeError;
+use crate::types::*;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "trace")]
+use tracing::{debug, warn};
+
+// 未指定 bot 時使用的預設 bot
+pub const DEFAULT_BOT: &str = "GPT-4o-Mini";
+
+// 命令列工具的配置，優先順序為：命令列參數 > 環境變數 > 配置文件
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CliConfig {
+    pub access_key: Option<String>,
+    pub bot: Option<String>,
+    pub base_url: String,
+    pub file_upload_url: String,
+    pub gql_url: String,
+}
+
+impl Default for CliConfig {
+    fn default() -> Self {
+        Self {
+            access_key: None,
+            bot: None,
+            base_url: DEFAULT_POE_BASE_URL.to_string(),
+            file_upload_url: DEFAULT_POE_FILE_UPLOAD_URL.to_string(),
+            gql_url: DEFAULT_POE_GQL_URL.to_string(),
+        }
+    }
+}
+
+impl CliConfig {
+    /// 預設配置文件路徑：$POE_CONFIG、$XDG_CONFIG_HOME/poe/config.toml 或 ~/.config/poe/config.toml
+    pub fn default_path() -> Option<PathBuf> {
+        if let Ok(path) = env::var("POE_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        let config_dir = env::var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|_| env::var("HOME").map(|home| Path::new(&home).join(".config")))
+            .ok()?;
+        Some(config_dir.join("poe").join("config.toml"))
+    }
+
+    /// 解析 TOML 格式的配置
+    pub fn from_toml(text: &str) -> Result<Self, PoeError> {
+        toml::from_str(text).map_err(|e| PoeError::ConfigError(e.to_string()))
+    }
+
+    /// 讀取配置文件並套用環境變數
+    ///
+    /// 指定的文件不存在時返回錯誤；使用預設路徑時文件可以不存在。
+    pub async fn load(path: Option<&Path>) -> Result<Self, PoeError> {
+        let (path, required) = match path {
+            Some(path) => (Some(path.to_path_buf()), true),
+            None => (Self::default_path(), false),
+        };
+
+        let mut config = match path {
+            Some(path) if path.exists() => {
+                #[cfg(feature = "trace")]
+                debug!("讀取配置文件: {}", path.display());
+                Self::from_toml(&tokio::fs::read_to_string(&path).await?)?
+            }
+            Some(path) if required => {
+                return Err(PoeError::ConfigError(format!(
+                    "配置文件不存在: {}",
+                    path.display()
+                )));
+            }
+            _ => Self::default(),
+        };
+        config.apply_env();
+        Ok(config)
+    }
+
+    /// 以環境變數覆蓋配置：POE_ACCESS_KEY、POE_BOT、POE_BASE_URL、POE_FILE_UPLOAD_URL、POE_GQL_URL
+    pub fn apply_env(&mut self) {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        if let Some(access_key) = var("POE_ACCESS_KEY") {
+            self.access_key = Some(access_key);
+        }
+        if let Some(bot) = var("POE_BOT") {
+            self.bot = Some(bot);
+        }
+        if let Some(base_url) = var("POE_BASE_URL") {
+            self.base_url = base_url;
+        }
+        if let Some(file_upload_url) = var("POE_FILE_UPLOAD_URL") {
+            self.file_upload_url = file_upload_url;
+        }
+        if let Some(gql_url) = var("POE_GQL_URL") {
+            self.gql_url = gql_url;
+        }
+    }
+
+    /// 實際使用的 bot 名稱
+    pub fn bot_name(&self) -> &str {
+        self.bot.as_deref().unwrap_or(DEFAULT_BOT)
+    }
+
+    /// 建立指定 bot 的客戶端，未配置 access key 時返回錯誤
+    pub fn client(&self, bot_name: &str) -> Result<PoeClient, PoeError> {
+        let access_key = self.access_key.as_deref().ok_or_else(|| {
+            PoeError::ConfigError("未設置 access key，請設置 POE_ACCESS_KEY 或配置文件".to_string())
+        })?;
+        Ok(PoeClient::new(
+            bot_name,
+            access_key,
+            &self.base_url,
+            &self.file_upload_url,
+        ))
+    }
+
+    /// 獲取模型列表，v1 為 true 時使用需要 access key 的 v1/models API
+    pub async fn model_list(
+        &self,
+        language_code: Option<&str>,
+        v1: bool,
+    ) -> Result<Vec<ModelInfo>, PoeError> {
+        let response = if v1 {
+            self.client("")?.get_v1_model_list().await?
+        } else {
+            get_model_list_from(&self.gql_url, language_code).await?
+        };
+        Ok(response.data)
+    }
+}
+
+// 命令列的對話會話，負責發送消息並維護對話記錄
+pub struct ChatSession {
+    client: PoeClient,
+    conversation: Conversation,
+}
+
+impl ChatSession {
+    pub fn new(client: PoeClient, conversation: Conversation) -> Self {
+        Self {
+            client,
+            conversation,
+        }
+    }
+
+    pub fn conversation(&self) -> &Conversation {
+        &self.conversation
+    }
+
+    /// 清空對話記錄，保留 bot 名稱
+    pub fn reset(&mut self) {
+        self.conversation = Conversation {
+            bot_name: self.conversation.bot_name.clone(),
+            ..Default::default()
+        };
+    }
+
+    /// 上傳本地檔案並轉換為附件
+    pub async fn upload_attachments(&self, paths: &[PathBuf]) -> Result<Vec<Attachment>, PoeError> {
+        self.client.prepare_attachments(paths).await
+    }
+
+    /// 發送消息並串流接收回覆，每個事件都會傳給 on_event，返回完整的回覆文本
+    ///
+    /// 發送失敗時該消息不會保留在對話記錄中。
+    pub async fn send(
+        &mut self,
+        message: ChatMessage,
+        on_event: impl FnMut(&ChatResponse),
+    ) -> Result<String, PoeError> {
+        self.conversation.push_message(message);
+        match self.stream_reply(on_event).await {
+            Ok(reply) => {
+                self.conversation.push_bot_reply(&reply);
+                Ok(reply)
+            }
+            Err(e) => {
+                #[cfg(feature = "trace")]
+                warn!("發送消息失敗: {}", e);
+                self.conversation.pop_message();
+                Err(e)
+            }
+        }
+    }
+
+    async fn stream_reply(
+        &self,
+        mut on_event: impl FnMut(&ChatResponse),
+    ) -> Result<String, PoeError> {
+        let mut stream = self
+            .client
+            .stream_request(self.conversation.to_request())
+            .await?;
+
+        let mut reply = String::new();
+        while let Some(response) = stream.next().await {
+            let response = response?;
+            on_event(&response);
+            match (&response.event, &response.data) {
+                (_, Some(ChatResponseData::Error { text, .. })) => {
+                    return Err(PoeError::BotError(text.clone()));
+                }
+                (ChatEventType::Text, Some(ChatResponseData::Text { text })) => {
+                    reply.push_str(text);
+                }
+                (ChatEventType::ReplaceResponse, Some(ChatResponseData::Text { text })) => {
+                    reply = text.clone();
+                }
+                (ChatEventType::Done, _) => break,
+                _ => {}
+            }
+        }
+        Ok(reply)
+    }
+}
+
+/// 依副檔名讀取對話記錄，.jsonl 使用 JSONL 格式，其餘使用 JSON 格式
+pub async fn load_conversation(path: &Path) -> Result<Conversation, PoeError> {
+    if is_jsonl(path) {
+        Conversation::load_jsonl(path).await
+    } else {
+        Conversation::load_json(path).await
+    }
+}
+
+/// 依副檔名保存對話記錄，.jsonl 使用 JSONL 格式，其餘使用 JSON 格式
+pub async fn save_conversation(conversation: &Conversation, path: &Path) -> Result<(), PoeError> {
+    if is_jsonl(path) {
+        conversation.save_jsonl(path).await
+    } else {
+        conversation.save_json(path).await
+    }
+}
+
+fn is_jsonl(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "jsonl")
+}
+
+// 命令列輸出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+// 模型列表過濾條件，模式支援 * 及 ? 通配符，不含通配符時按子字串匹配（不分大小寫）
+#[derive(Debug, Clone, Default)]
+pub struct ModelFilter {
+    pub owner: Option<String>,
+    pub name: Option<String>,
+}
+
+impl ModelFilter {
+    pub fn matches(&self, model: &ModelInfo) -> bool {
+        let matches = |pattern: &Option<String>, text: &str| {
+            pattern
+                .as_deref()
+                .is_none_or(|pattern| pattern_matches(pattern, text))
+        };
+        matches(&self.owner, &model.owned_by) && matches(&self.name, &model.id)
+    }
+
+    pub fn apply(&self, models: Vec<ModelInfo>) -> Vec<ModelInfo> {
+        models
+            .into_iter()
+            .filter(|model| self.matches(model))
+            .collect()
+    }
+}
+
+/// 以通配符模式匹配文本（不分大小寫），不含通配符時按子字串匹配
+pub fn pattern_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+    if !pattern.contains(['*', '?']) {
+        return text.contains(&pattern);
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // 回溯匹配，記錄最近一個 * 的位置
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// 將模型列表輸出為指定格式
+pub fn render_models(models: &[ModelInfo], format: OutputFormat) -> Result<String, PoeError> {
+    let headers = ["id", "owned_by", "created"];
+    let rows: Vec<Vec<String>> = models
+        .iter()
+        .map(|model| {
+            vec![
+                model.id.clone(),
+                model.owned_by.clone(),
+                model.created.to_string(),
+            ]
+        })
+        .collect();
+    render(models, &headers, &rows, format)
+}
+
+// 單個檔案的上傳結果
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadRecord {
+    pub source: String,
+    #[serde(flatten)]
+    pub response: FileUploadResponse,
+}
+
+/// 將上傳結果輸出為指定格式
+pub fn render_uploads(records: &[UploadRecord], format: OutputFormat) -> Result<String, PoeError> {
+    let headers = ["source", "attachment_url", "mime_type", "size"];
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| {
+            vec![
+                record.source.clone(),
+                record.response.attachment_url.clone(),
+                record.response.mime_type.clone().unwrap_or_default(),
+                record
+                    .response
+                    .size
+                    .map(|size| size.to_string())
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect();
+    render(records, &headers, &rows, format)
+}
+
+/// 依來源建立上傳請求，http(s) 地址視為遠端檔案
+pub fn upload_request(source: &str, mime_type: Option<&str>) -> FileUploadRequest {
+    match FileUploadRequest::from_source(source) {
+        FileUploadRequest::LocalFile { file, .. } => FileUploadRequest::LocalFile {
+            file,
+            mime_type: mime_type.map(str::to_string),
+        },
+        request => request,
+    }
+}
+
+fn render<T: Serialize>(
+    items: &[T],
+    headers: &[&str],
+    rows: &[Vec<String>],
+    format: OutputFormat,
+) -> Result<String, PoeError> {
+    Ok(match format {
+        OutputFormat::Json => serde_json::to_string_pretty(items)?,
+        OutputFormat::Csv => render_csv(headers, rows),
+        OutputFormat::Table => render_table(headers, rows),
+    })
+}
+
+fn render_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let header: Vec<String> = headers.iter().map(|header| header.to_uppercase()).collect();
+    let mut lines = vec![format_row(&header)];
+    lines.extend(rows.iter().map(|row| format_row(row)));
+    lines.join("\n")
+}
+
+fn render_csv(headers: &[&str], rows: &[Vec<String>]) -> String {
+    // 含逗號、引號或換行的欄位需要加上引號
+    let escape = |cell: &str| {
+        if cell.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", cell.replace('"', "\"\""))
+        } else {
+            cell.to_string()
+        }
+    };
+
+    let mut lines = vec![headers.join(",")];
+    lines.extend(rows.iter().map(|row| {
+        row.iter()
+            .map(|cell| escape(cell))
+            .collect::<Vec<_>>()
+            .join(",")
+    }));
+    lines.join("\n")
+}
@@ -1,21 +1,45 @@
+use crate::download::{
+    InlineTarget, create_unique_file, data_uri, rewrite_inline_refs, safe_file_name,
+};
 use crate::error::PoeError;
+use crate::mime::{SNIFF_LEN, detect_file_mime_type, detect_mime_type};
+use crate::openai::{ChatCompletion, ChatCompletionChunk, ChatCompletionRequest, StreamOptions};
+use crate::sse::{SseDecoder, check_pending_line};
 use crate::types::*;
+use crate::upload::{
+    BatchProgress, BatchUploadItem, CountingStream, UploadOptions, UploadPolicy, UploadProgress,
+    is_retryable,
+};
+use crate::upload_cache::UploadCache;
+use bytes::Bytes;
 use futures_util::Stream;
 use futures_util::StreamExt;
-use futures_util::future::join_all;
 use reqwest::Client;
 use reqwest::header::{COOKIE, HeaderMap, HeaderValue};
 use serde_json::Value;
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio_util::io::ReaderStream;
 #[cfg(feature = "trace")]
 use tracing::{debug, warn};
 
-const POE_GQL_URL: &str = "https://poe.com/api/gql_POST";
+// 預設的 Poe API 地址
+pub const DEFAULT_POE_BASE_URL: &str = "https://api.poe.com";
+// 預設的檔案上傳地址
+pub const DEFAULT_POE_FILE_UPLOAD_URL: &str =
+    "https://www.quora.com/poe_api/file_upload_3RD_PARTY_POST";
+
+// 預設的模型列表 GraphQL 地址
+pub const DEFAULT_POE_GQL_URL: &str = "https://poe.com/api/gql_POST";
 const POE_GQL_MODEL_HASH: &str = "b24b2f2f6da147b3345eec1a433ed17b6e1332df97dea47622868f41078a40cc";
 const POE_GQL_MODEL_REVISION: &str = "e2acc7025b43e08e88164ba8105273f37fbeaa26";
 
+// 下載檔案時根據 Content-Length 預先分配的最大容量
+const MAX_DOWNLOAD_PREALLOCATION: u64 = 1024 * 1024;
+
 #[derive(Clone)]
 pub struct PoeClient {
     client: Client,
@@ -23,6 +47,9 @@ pub struct PoeClient {
     access_key: String,
     poe_base_url: String,
     poe_file_upload_url: String,
+    upload_policy: UploadPolicy,
+    upload_cache: Option<UploadCache>,
+    max_download_size: Option<u64>,
 }
 
 impl PoeClient {
@@ -54,16 +81,61 @@ impl PoeClient {
             access_key: access_key.to_string(),
             poe_base_url: normalized_base_url,
             poe_file_upload_url: normalized_file_upload_url,
+            upload_policy: UploadPolicy::default(),
+            upload_cache: None,
+            max_download_size: None,
         }
     }
 
+    /// 設置上傳前檢查的規則
+    pub fn with_upload_policy(mut self, upload_policy: UploadPolicy) -> Self {
+        self.upload_policy = upload_policy;
+        self
+    }
+
+    /// 設置上傳快取，內容相同的檔案或相同的遠端地址會直接返回先前的上傳結果
+    pub fn with_upload_cache(mut self, upload_cache: UploadCache) -> Self {
+        self.upload_cache = Some(upload_cache);
+        self
+    }
+
+    /// 設置下載 bot 產生檔案的大小上限（字節）
+    pub fn with_max_download_size(mut self, max_download_size: u64) -> Self {
+        self.max_download_size = Some(max_download_size);
+        self
+    }
+
     pub async fn stream_request(
         &self,
+        request: ChatRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>, PoeError> {
+        self.stream_request_to(&self.bot_name, request).await
+    }
+
+    /// 以轉發的身份調用另一個 bot，保留原請求的 user_id、conversation_id 及 message_id
+    pub async fn stream_bot_request(
+        &self,
+        target_bot: &str,
+        incoming: &ChatRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>, PoeError> {
+        #[cfg(feature = "trace")]
+        debug!(
+            "轉發請求至 bot: {}，conversation_id: {}",
+            target_bot, incoming.conversation_id
+        );
+        self.stream_request_to(target_bot, incoming.forwarded())
+            .await
+    }
+
+    /// 向指定 bot 發送串流請求
+    pub async fn stream_request_to(
+        &self,
+        bot_name: &str,
         #[cfg(feature = "xml")] mut request: ChatRequest,
         #[cfg(not(feature = "xml"))] request: ChatRequest,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>, PoeError> {
         #[cfg(feature = "trace")]
-        debug!("開始串流請求，bot_name: {}", self.bot_name);
+        debug!("開始串流請求，bot_name: {}", bot_name);
 
         // 當啟用 xml feature 時，自動將工具轉換為 XML 格式
         #[cfg(feature = "xml")]
@@ -91,7 +163,7 @@ impl PoeClient {
             }
         }
 
-        let url = format!("{}/bot/{}", self.poe_base_url, self.bot_name);
+        let url = format!("{}/bot/{}", self.poe_base_url, bot_name);
         #[cfg(feature = "trace")]
         debug!("發送請求至 URL: {}", url);
 
@@ -119,616 +191,39 @@ impl PoeClient {
         #[cfg(feature = "trace")]
         debug!("成功接收到串流回應");
 
-        let mut static_buffer = String::new();
-        let mut current_event: Option<ChatEventType> = None;
-        let mut is_collecting_data = false;
-        // 用於累積 tool_calls 的狀態
-        let mut accumulated_tool_calls: Vec<PartialToolCall> = Vec::new();
-        let mut tool_calls_complete = false;
-
-        // XML 工具調用緩衝和檢測狀態
-        #[cfg(feature = "xml")]
-        let mut xml_text_buffer = String::new();
-        #[cfg(feature = "xml")]
-        let mut xml_detection_active = false;
+        let mut decoder = SseDecoder::new();
         #[cfg(feature = "xml")]
-        let available_tools = request.tools.clone().unwrap_or_default();
+        {
+            decoder = decoder.with_tools(request.tools.clone().unwrap_or_default());
+        }
 
+        // 回應結束時以 None 標記，處理緩衝區中未以換行結尾的最後一行
         let stream = response
             .bytes_stream()
-            .map(move |result| {
-                result.map_err(PoeError::from).map(|chunk| {
-                    let chunk_str = String::from_utf8_lossy(&chunk);
-                    #[cfg(feature = "trace")]
-                    debug!("處理串流塊，大小: {} 字節", chunk.len());
-
-                    let mut events = Vec::new();
-                    // 將新的塊添加到靜態緩衝區
-                    static_buffer.push_str(&chunk_str);
-
-                    // 尋找完整的消息
-                    while let Some(newline_pos) = static_buffer.find('\n') {
-                        let line = static_buffer[..newline_pos].trim().to_string();
-                        static_buffer = static_buffer[newline_pos + 1..].to_string();
-
-                        if line.is_empty() {
-                            // 重置當前事件狀態，準備處理下一個事件
-                            current_event = None;
-                            is_collecting_data = false;
-                            continue;
-                        }
-
-                        if line == ": ping" {
-                            #[cfg(feature = "trace")]
-                            debug!("收到 ping 訊號");
-                            continue;
-                        }
-
-                        if line.starts_with("event: ") {
-                            let event_name = line.trim_start_matches("event: ").trim();
-                            #[cfg(feature = "trace")]
-                            debug!("解析事件類型: {}", event_name);
-
-                            let event_type = match event_name {
-                                "text" => ChatEventType::Text,
-                                "replace_response" => ChatEventType::ReplaceResponse,
-                                "json" => ChatEventType::Json,
-                                "file" => ChatEventType::File,
-                                "done" => ChatEventType::Done,
-                                "error" => ChatEventType::Error,
-                                _ => {
-                                    #[cfg(feature = "trace")]
-                                    warn!("收到未知事件類型: {}", event_name);
-                                    continue;
-                                }
-                            };
-
-                            current_event = Some(event_type);
-                            is_collecting_data = false;
-                            continue;
-                        }
-
-                        if line.starts_with("data: ") {
-                            let data = line.trim_start_matches("data: ").trim();
-                            #[cfg(feature = "trace")]
-                            debug!(
-                                "收到事件數據: {}",
-                                if data.len() > 100 { &data[..100] } else { data }
-                            );
-
-                            if let Some(ref event_type) = current_event {
-                                match event_type {
-                                    ChatEventType::Text | ChatEventType::ReplaceResponse => {
-                                        if let Ok(json) = serde_json::from_str::<Value>(data) {
-                                            if let Some(text) = json.get("text").and_then(Value::as_str) {
-                                                #[cfg(feature = "trace")]
-                                                debug!("解析到文本數據，長度: {}", text.len());
-
-                                                // XML 工具調用檢測和緩衝邏輯
-                                                #[cfg(feature = "xml")]
-                                                {
-                                                    // 基於實際工具定義的智能檢測
-                                                    let should_start_xml_detection = !xml_detection_active && (
-                                                        text.contains("<tool_call>") ||
-                                                        text.contains("<invoke") ||
-                                                        // 檢查是否包含任何已定義的工具名稱標籤
-                                                        available_tools.iter().any(|tool|
-                                                            text.contains(&format!("<{}>", tool.function.name))
-                                                        )
-                                                    );
-                                                    if should_start_xml_detection {
-                                                        xml_detection_active = true;
-                                                        xml_text_buffer.clear();
-                                                        #[cfg(feature = "trace")]
-                                                        debug!("檢測到已定義工具的 XML 調用，開始 XML 緩衝 | 清空緩衝區重新開始");
-                                                    }
-                                                    if xml_detection_active {
-                                                        xml_text_buffer.push_str(text);
-                                                        #[cfg(feature = "trace")]
-                                                        debug!("XML 模式：文本已添加到緩衝區 | 長度: {}", xml_text_buffer.len());
-                                                        // 檢查是否有完整的工具調用
-                                                        let message = ChatMessage {
-                                                            role: "assistant".to_string(),
-                                                            content: xml_text_buffer.clone(),
-                                                            attachments: None,
-                                                            content_type: "text/plain".to_string(),
-                                                        };
-                                                        // 使用工具定義來檢測和解析
-                                                        if message.contains_xml_tool_calls_with_tools(&available_tools) {
-                                                            let tool_calls = message.extract_xml_tool_calls_with_tools(&available_tools);
-                                                            if !tool_calls.is_empty() {
-                                                                #[cfg(feature = "trace")]
-                                                                debug!("檢測到完整的 XML 工具調用，轉換為標準格式，數量: {}", tool_calls.len());
-                                                                // 發送工具調用事件
-                                                                events.push(Ok(ChatResponse {
-                                                                    event: ChatEventType::Json,
-                                                                    data: Some(ChatResponseData::ToolCalls(tool_calls)),
-                                                                }));
-                                                                // 移除 XML 部分並發送剩餘文本
-                                                                let clean_text = Self::remove_xml_tool_calls(&xml_text_buffer);
-                                                                if !clean_text.trim().is_empty() {
-                                                                    events.push(Ok(ChatResponse {
-                                                                        event: event_type.clone(),
-                                                                        data: Some(ChatResponseData::Text {
-                                                                            text: clean_text,
-                                                                        }),
-                                                                    }));
-                                                                }
-                                                                // 重置 XML 緩衝狀態
-                                                                xml_text_buffer.clear();
-                                                                xml_detection_active = false;
-                                                            } else {
-                                                                // 沒有完整的工具調用，繼續緩衝
-                                                                #[cfg(feature = "trace")]
-                                                                debug!("XML 工具調用尚未完整，繼續緩衝");
-                                                            }
-                                                        } else {
-                                                            // 檢查是否應該釋放緩衝區
-                                                            let should_release = xml_text_buffer.contains('\n') &&
-                                                                 xml_text_buffer.len() > 200 &&
-                                                                 !available_tools.iter().any(|tool|
-                                                                     xml_text_buffer.contains(&format!("<{}>", tool.function.name)) ||
-                                                                     xml_text_buffer.contains(&format!("</{}>", tool.function.name))
-                                                                 ) &&
-                                                                 !xml_text_buffer.contains("<tool_call>") &&
-                                                                 !xml_text_buffer.contains("<invoke");
-                                                            if should_release {
-                                                                #[cfg(feature = "trace")]
-                                                                debug!("XML 緩衝區過大或不包含工具調用，發送為普通文本");
-                                                                // 發送緩衝的文本
-                                                                events.push(Ok(ChatResponse {
-                                                                    event: event_type.clone(),
-                                                                    data: Some(ChatResponseData::Text {
-                                                                        text: xml_text_buffer.clone(),
-                                                                    }),
-                                                                }));
-                                                                // 重置緩衝狀態
-                                                                xml_text_buffer.clear();
-                                                                xml_detection_active = false;
-                                                            } else {
-                                                                // 繼續緩衝
-                                                                #[cfg(feature = "trace")]
-                                                                debug!("繼續緩衝 XML 文本，當前長度: {}", xml_text_buffer.len());
-                                                            }
-                                                        }
-                                                    } else {
-                                                        // 沒有檢測到 XML，直接發送文本
-                                                        events.push(Ok(ChatResponse {
-                                                            event: event_type.clone(),
-                                                            data: Some(ChatResponseData::Text {
-                                                                text: text.to_string(),
-                                                            }),
-                                                        }));
-                                                    }
-                                                }
-
-                                                #[cfg(not(feature = "xml"))]
-                                                {
-                                                    events.push(Ok(ChatResponse {
-                                                        event: event_type.clone(),
-                                                        data: Some(ChatResponseData::Text {
-                                                            text: text.to_string(),
-                                                        }),
-                                                    }));
-                                                }
-                                            }
-                                        } else {
-                                            #[cfg(feature = "trace")]
-                                            debug!("JSON 解析失敗，可能是不完整的數據，等待更多數據");
-                                            is_collecting_data = true;
-                                        }
-                                    }
-                                    ChatEventType::File => {
-                                        if let Ok(file_data) = serde_json::from_str::<FileData>(data) {
-                                            #[cfg(feature = "trace")]
-                                            debug!("解析到文件數據: {}", file_data.name);
-                                            events.push(Ok(ChatResponse {
-                                                event: ChatEventType::File,
-                                                data: Some(ChatResponseData::File(file_data)),
-                                            }));
-                                        } else {
-                                            #[cfg(feature = "trace")]
-                                            debug!("文件數據 JSON 解析失敗，可能是不完整的數據，等待更多數據");
-                                            is_collecting_data = true;
-                                        }
-                                    }
-                                    ChatEventType::Json => {
-                                        if let Ok(json) = serde_json::from_str::<Value>(data) {
-                                            #[cfg(feature = "trace")]
-                                            debug!("解析到 JSON 事件數據");
-                                            // 檢查是否有 finish_reason: "tool_calls"，表示工具調用完成
-                                            let finish_reason = json
-                                                .get("choices")
-                                                .and_then(|choices| choices.get(0))
-                                                .and_then(|choice| choice.get("finish_reason"))
-                                                .and_then(Value::as_str);
-
-                                            if finish_reason == Some("tool_calls") {
-                                                #[cfg(feature = "trace")]
-                                                debug!("檢測到工具調用完成標誌");
-                                                tool_calls_complete = true;
-                                            }
-
-                                            // 檢查是否包含 tool_calls delta
-                                            let tool_calls_delta = json
-                                                .get("choices")
-                                                .and_then(|choices| choices.get(0))
-                                                .and_then(|choice| choice.get("delta"))
-                                                .and_then(|delta| delta.get("tool_calls"));
-
-                                            if let Some(tool_calls_array) = tool_calls_delta {
-                                                #[cfg(feature = "trace")]
-                                                debug!("檢測到工具調用 delta");
-                                                // 處理每個工具調用的 delta
-                                                if let Some(tool_calls) = tool_calls_array.as_array() {
-                                                    for tool_call_delta in tool_calls {
-                                                        let index = tool_call_delta
-                                                            .get("index")
-                                                            .and_then(Value::as_u64)
-                                                            .unwrap_or(0)
-                                                            as usize;
-
-                                                        // 確保 accumulated_tool_calls 有足夠的元素
-                                                        while accumulated_tool_calls.len() <= index {
-                                                            accumulated_tool_calls.push(PartialToolCall::default());
-                                                        }
-
-                                                        // 更新 id 和 type
-                                                        if let Some(id) = tool_call_delta
-                                                            .get("id")
-                                                            .and_then(Value::as_str)
-                                                        {
-                                                            accumulated_tool_calls[index].id = id.to_string();
-                                                        }
-
-                                                        if let Some(type_str) = tool_call_delta
-                                                            .get("type")
-                                                            .and_then(Value::as_str)
-                                                        {
-                                                            accumulated_tool_calls[index].r#type = type_str.to_string();
-                                                        }
-
-                                                        // 更新 function 相關欄位
-                                                        if let Some(function) = tool_call_delta.get("function") {
-                                                            if let Some(name) = function
-                                                                .get("name")
-                                                                .and_then(Value::as_str)
-                                                            {
-                                                                accumulated_tool_calls[index].function_name = name.to_string();
-                                                            }
-
-                                                            if let Some(args) = function
-                                                                .get("arguments")
-                                                                .and_then(Value::as_str)
-                                                            {
-                                                                accumulated_tool_calls[index].function_arguments.push_str(args);
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            } else if !tool_calls_complete {
-                                                // 如果沒有 tool_calls delta 且工具調用尚未完成，
-                                                // 則按一般 JSON 處理
-                                                events.push(Ok(ChatResponse {
-                                                    event: ChatEventType::Json,
-                                                    data: Some(ChatResponseData::Text {
-                                                        text: data.to_string(),
-                                                    }),
-                                                }));
-                                            }
-                                        } else {
-                                            #[cfg(feature = "trace")]
-                                            debug!("JSON 事件解析失敗，可能是不完整的數據");
-                                            is_collecting_data = true;
-                                        }
-                                    }
-                                    ChatEventType::Done => {
-                                        #[cfg(feature = "trace")]
-                                        debug!("收到完成事件");
-                                        // 處理任何剩餘的 XML 緩衝內容
-                                        #[cfg(feature = "xml")]
-                                        {
-                                            if xml_detection_active && !xml_text_buffer.trim().is_empty() {
-                                                #[cfg(feature = "trace")]
-                                                debug!("處理剩餘的 XML 緩衝內容，長度: {}", xml_text_buffer.len());
-                                                let message = ChatMessage {
-                                                    role: "assistant".to_string(),
-                                                    content: xml_text_buffer.clone(),
-                                                    attachments: None,
-                                                    content_type: "text/plain".to_string(),
-                                                };
-                                                // 使用工具定義來檢測和解析
-                                                if message.contains_xml_tool_calls_with_tools(&available_tools) {
-                                                    let tool_calls = message.extract_xml_tool_calls_with_tools(&available_tools);
-                                                    if !tool_calls.is_empty() {
-                                                        #[cfg(feature = "trace")]
-                                                        debug!("在完成事件中檢測到 XML 工具調用，數量: {}", tool_calls.len());
-                                                        // 發送工具調用事件
-                                                        events.push(Ok(ChatResponse {
-                                                            event: ChatEventType::Json,
-                                                            data: Some(ChatResponseData::ToolCalls(tool_calls)),
-                                                        }));
-                                                        // 發送清理後的文本（如果有）
-                                                        let clean_text = Self::remove_xml_tool_calls(&xml_text_buffer);
-                                                        if !clean_text.trim().is_empty() {
-                                                            events.push(Ok(ChatResponse {
-                                                                event: ChatEventType::Text,
-                                                                data: Some(ChatResponseData::Text {
-                                                                    text: clean_text,
-                                                                }),
-                                                            }));
-                                                        }
-                                                    } else {
-                                                        // 發送為普通文本
-                                                        events.push(Ok(ChatResponse {
-                                                            event: ChatEventType::Text,
-                                                            data: Some(ChatResponseData::Text {
-                                                                text: xml_text_buffer.clone(),
-                                                            }),
-                                                        }));
-                                                    }
-                                                } else {
-                                                    // 發送為普通文本
-                                                    events.push(Ok(ChatResponse {
-                                                        event: ChatEventType::Text,
-                                                        data: Some(ChatResponseData::Text {
-                                                            text: xml_text_buffer.clone(),
-                                                        }),
-                                                    }));
-                                                }
-                                                // 清理緩衝狀態
-                                                xml_text_buffer.clear();
-                                                xml_detection_active = false;
-                                            }
-                                        }
-                                        events.push(Ok(ChatResponse {
-                                            event: ChatEventType::Done,
-                                            data: Some(ChatResponseData::Empty),
-                                        }));
-                                        current_event = None;
-                                    }
-                                    ChatEventType::Error => {
-                                        if let Ok(json) = serde_json::from_str::<Value>(data) {
-                                            let text = json
-                                                .get("text")
-                                                .and_then(Value::as_str)
-                                                .unwrap_or("未知錯誤");
-                                            let allow_retry = json
-                                                .get("allow_retry")
-                                                .and_then(Value::as_bool)
-                                                .unwrap_or(false);
-
-                                            #[cfg(feature = "trace")]
-                                            warn!("收到錯誤事件: {}, 可重試: {}", text, allow_retry);
-
-                                            events.push(Ok(ChatResponse {
-                                                event: ChatEventType::Error,
-                                                data: Some(ChatResponseData::Error {
-                                                    text: text.to_string(),
-                                                    allow_retry,
-                                                }),
-                                            }));
-                                        } else {
-                                            #[cfg(feature = "trace")]
-                                            warn!("無法解析錯誤事件數據: {}", data);
-                                        }
-                                        current_event = None;
-                                    }
-                                }
-                            } else {
-                                #[cfg(feature = "trace")]
-                                debug!("收到數據但沒有當前事件類型");
-                            }
-                        } else if is_collecting_data {
-                            // 嘗試解析累積的 JSON
-                            #[cfg(feature = "trace")]
-                            debug!("嘗試解析未完整的 JSON 數據: {}", line);
-
-                            if let Some(ref event_type) = current_event {
-                                match event_type {
-                                    ChatEventType::Text | ChatEventType::ReplaceResponse => {
-                                        if let Ok(json) = serde_json::from_str::<Value>(&line) {
-                                            if let Some(text) = json.get("text").and_then(Value::as_str) {
-                                                #[cfg(feature = "trace")]
-                                                debug!("成功解析到累積的 JSON 文本，長度: {}", text.len());
-
-                                                events.push(Ok(ChatResponse {
-                                                    event: event_type.clone(),
-                                                    data: Some(ChatResponseData::Text {
-                                                        text: text.to_string(),
-                                                    }),
-                                                }));
-                                                is_collecting_data = false;
-                                                current_event = None;
-                                            }
-                                        }
-                                    }
-                                    ChatEventType::File => {
-                                        if let Ok(file_data) = serde_json::from_str::<FileData>(&line) {
-                                            #[cfg(feature = "trace")]
-                                            debug!("成功解析到累積的文件數據: {}", file_data.name);
-
-                                            events.push(Ok(ChatResponse {
-                                                event: ChatEventType::File,
-                                                data: Some(ChatResponseData::File(file_data)),
-                                            }));
-                                            is_collecting_data = false;
-                                            current_event = None;
-                                        }
-                                    }
-                                    ChatEventType::Json => {
-                                        if let Ok(json) = serde_json::from_str::<Value>(&line) {
-                                            #[cfg(feature = "trace")]
-                                            debug!("成功解析到累積的 JSON 事件數據");
-
-                                            // 檢查是否有 finish_reason: "tool_calls"
-                                            let finish_reason = json
-                                                .get("choices")
-                                                .and_then(|choices| choices.get(0))
-                                                .and_then(|choice| choice.get("finish_reason"))
-                                                .and_then(Value::as_str);
-
-                                            if finish_reason == Some("tool_calls") {
-                                                #[cfg(feature = "trace")]
-                                                debug!("檢測到工具調用完成標誌");
-                                                tool_calls_complete = true;
-                                            }
-
-                                            // 檢查是否包含 tool_calls delta
-                                            let tool_calls_delta = json
-                                                .get("choices")
-                                                .and_then(|choices| choices.get(0))
-                                                .and_then(|choice| choice.get("delta"))
-                                                .and_then(|delta| delta.get("tool_calls"));
-
-                                            if let Some(tool_calls_array) = tool_calls_delta {
-                                                #[cfg(feature = "trace")]
-                                                debug!("檢測到工具調用 delta");
-
-                                                // 處理每個工具調用的 delta
-                                                if let Some(tool_calls) = tool_calls_array.as_array() {
-                                                    for tool_call_delta in tool_calls {
-                                                        let index = tool_call_delta
-                                                            .get("index")
-                                                            .and_then(Value::as_u64)
-                                                            .unwrap_or(0)
-                                                            as usize;
-
-                                                        // 確保 accumulated_tool_calls 有足夠的元素
-                                                        while accumulated_tool_calls.len() <= index {
-                                                            accumulated_tool_calls.push(PartialToolCall::default());
-                                                        }
-
-                                                        // 更新 id 和 type
-                                                        if let Some(id) = tool_call_delta
-                                                            .get("id")
-                                                            .and_then(Value::as_str)
-                                                        {
-                                                            accumulated_tool_calls[index].id = id.to_string();
-                                                        }
-
-                                                        if let Some(type_str) = tool_call_delta
-                                                            .get("type")
-                                                            .and_then(Value::as_str)
-                                                        {
-                                                            accumulated_tool_calls[index].r#type = type_str.to_string();
-                                                        }
-
-                                                        // 更新 function 相關欄位
-                                                        if let Some(function) = tool_call_delta.get("function") {
-                                                            if let Some(name) = function
-                                                                .get("name")
-                                                                .and_then(Value::as_str)
-                                                            {
-                                                                accumulated_tool_calls[index].function_name = name.to_string();
-                                                            }
-
-                                                            if let Some(args) = function
-                                                                .get("arguments")
-                                                                .and_then(Value::as_str)
-                                                            {
-                                                                accumulated_tool_calls[index].function_arguments.push_str(args);
-                                                            }
-                                                        }
-                                                    }
-                                                }
-
-                                                // 如果工具調用完成，則創建並發送 ChatResponse
-                                                if tool_calls_complete && !accumulated_tool_calls.is_empty() {
-                                                    let complete_tool_calls = accumulated_tool_calls
-                                                        .iter()
-                                                        .filter(|tc| {
-                                                            !tc.id.is_empty() && !tc.function_name.is_empty()
-                                                        })
-                                                        .map(|tc| ChatToolCall {
-                                                            id: tc.id.clone(),
-                                                            r#type: tc.r#type.clone(),
-                                                            function: FunctionCall {
-                                                                name: tc.function_name.clone(),
-                                                                arguments: tc.function_arguments.clone(),
-                                                            },
-                                                        })
-                                                        .collect::<Vec<ChatToolCall>>();
-
-                                                    if !complete_tool_calls.is_empty() {
-                                                        #[cfg(feature = "trace")]
-                                                        debug!("發送完整的工具調用，數量: {}", complete_tool_calls.len());
-
-                                                        events.push(Ok(ChatResponse {
-                                                            event: ChatEventType::Json,
-                                                            data: Some(ChatResponseData::ToolCalls(complete_tool_calls)),
-                                                        }));
-
-                                                        // 重置累積狀態
-                                                        accumulated_tool_calls.clear();
-                                                        tool_calls_complete = false;
-                                                    }
-                                                }
-                                            } else {
-                                                // 如果沒有 tool_calls delta，則按一般 JSON 處理
-                                                events.push(Ok(ChatResponse {
-                                                    event: ChatEventType::Json,
-                                                    data: Some(ChatResponseData::Text {
-                                                        text: line.to_string(),
-                                                    }),
-                                                }));
-                                            }
-
-                                            is_collecting_data = false;
-                                            current_event = None;
-                                        }
-                                    }
-                                    ChatEventType::Done | ChatEventType::Error => {
-                                        // 這些事件類型不應該有累積的數據
-                                        is_collecting_data = false;
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    // 在處理完 chunk 中的所有行之後，檢查是否需要發送最終的 tool_calls 事件
-                    if tool_calls_complete && !accumulated_tool_calls.is_empty() {
-                        let complete_tool_calls = accumulated_tool_calls
-                            .iter()
-                            .filter(|tc| !tc.id.is_empty() && !tc.function_name.is_empty())
-                            .map(|tc| ChatToolCall {
-                                id: tc.id.clone(),
-                                r#type: tc.r#type.clone(),
-                                function: FunctionCall {
-                                    name: tc.function_name.clone(),
-                                    arguments: tc.function_arguments.clone(),
-                                },
-                            })
-                            .collect::<Vec<ChatToolCall>>();
-
-                        if !complete_tool_calls.is_empty() {
-                            #[cfg(feature = "trace")]
-                            debug!("發送最終的完整工具調用，數量: {}", complete_tool_calls.len());
-
-                            events.push(Ok(ChatResponse {
-                                event: ChatEventType::Json,
-                                data: Some(ChatResponseData::ToolCalls(complete_tool_calls)),
-                            }));
-
-                            // 重置狀態
-                            accumulated_tool_calls.clear();
-                            tool_calls_complete = false;
-                        }
-                    }
-
-                    events
-                })
+            .map(Some)
+            .chain(futures_util::stream::once(async { None }))
+            .map(move |item| match item {
+                Some(Ok(chunk)) => decoder.push(&chunk),
+                Some(Err(e)) => Err(PoeError::from(e)),
+                None => Ok(decoder.finish()),
             })
             .flat_map(|result| {
                 futures_util::stream::iter(match result {
-                    Ok(events) => events,
+                    Ok(events) => events.into_iter().map(Ok).collect(),
                     Err(e) => {
                         #[cfg(feature = "trace")]
                         warn!("串流處理錯誤: {}", e);
                         vec![Err(e)]
                     }
                 })
+            })
+            // 出錯後結束串流，不再讀取剩餘的回應
+            .scan(false, |failed, result| {
+                if *failed {
+                    return futures_util::future::ready(None);
+                }
+                *failed = result.is_err();
+                futures_util::future::ready(Some(result))
             });
 
         Ok(Box::pin(stream))
@@ -798,57 +293,208 @@ impl PoeClient {
         file_path: &str,
         mime_type: Option<&str>,
     ) -> Result<FileUploadResponse, PoeError> {
+        self.upload_local_file_with(file_path, mime_type, &UploadOptions::default())
+            .await
+    }
+
+    /// 上傳本地檔案，並通過 options 回報進度或取消上傳
+    pub async fn upload_local_file_with(
+        &self,
+        file_path: &str,
+        mime_type: Option<&str>,
+        options: &UploadOptions,
+    ) -> Result<FileUploadResponse, PoeError> {
+        options.check_cancelled()?;
         #[cfg(feature = "trace")]
         debug!(
             "開始上傳本地檔案: {} | MIME 類型: {:?}",
             file_path, mime_type
         );
-        // 檢查檔案是否存在
+        // 檢查檔案是否存在，並在建立連接前根據 metadata 檢查大小
         let path = Path::new(file_path);
-        if !path.exists() {
-            #[cfg(feature = "trace")]
-            warn!("檔案不存在: {}", file_path);
-            return Err(PoeError::FileNotFound(file_path.to_string()));
-        }
+        let metadata = match tokio::fs::metadata(path).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => {
+                #[cfg(feature = "trace")]
+                warn!("檔案不存在: {}", file_path);
+                return Err(PoeError::FileNotFound(file_path.to_string()));
+            }
+        };
+        self.upload_policy
+            .check_file_size(file_path, metadata.len())?;
 
-        // 簡化 MIME 類型處理：如果有提供 mime_type 就使用，否則使用預設值
-        let content_type = mime_type.unwrap_or("application/octet-stream").to_string();
+        // 優先使用指定的 mime_type，否則根據副檔名及檔案內容自動檢測
+        let content_type = match mime_type {
+            Some(mime_type) => mime_type.to_string(),
+            None => detect_file_mime_type(path).await?.to_string(),
+        };
 
         #[cfg(feature = "trace")]
         debug!("使用 MIME 類型: {}", content_type);
 
-        // 建立 multipart 表單
-        let file = tokio::fs::File::open(path).await.map_err(|e| {
-            #[cfg(feature = "trace")]
-            warn!("無法開啟檔案: {}", e);
-            PoeError::FileReadError(e)
-        })?;
+        self.upload_policy.check_mime_type(&content_type)?;
 
-        let file_part =
-            reqwest::multipart::Part::stream(reqwest::Body::wrap_stream(ReaderStream::new(file)))
-                .file_name(
-                    path.file_name()
-                        .and_then(|name| name.to_str())
-                        .unwrap_or("file")
-                        .to_string(),
-                )
-                .mime_str(&content_type)
-                .map_err(|e| {
-                    #[cfg(feature = "trace")]
-                    warn!("設置 MIME 類型失敗: {}", e);
-                    PoeError::FileUploadFailed(format!("設置 MIME 類型失敗: {}", e))
-                })?;
+        let cache_key = match self.upload_cache {
+            Some(_) => Some(UploadCache::file_key(path).await?),
+            None => None,
+        };
+        self.cached_upload(cache_key, Some(metadata.len()), options, async {
+            let file = tokio::fs::File::open(path).await.map_err(|e| {
+                #[cfg(feature = "trace")]
+                warn!("無法開啟檔案: {}", e);
+                PoeError::FileReadError(e)
+            })?;
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("file");
+
+            self.send_reader_upload(
+                file,
+                file_name,
+                &content_type,
+                Some(metadata.len()),
+                options,
+            )
+            .await
+        })
+        .await
+    }
 
-        let form = reqwest::multipart::Form::new().part("file", file_part);
+    /// 上傳記憶體中的內容，未指定 mime_type 時根據檔名及內容自動檢測
+    pub async fn upload_bytes(
+        &self,
+        data: impl Into<Bytes>,
+        file_name: &str,
+        mime_type: Option<&str>,
+    ) -> Result<FileUploadResponse, PoeError> {
+        self.upload_bytes_with(data, file_name, mime_type, &UploadOptions::default())
+            .await
+    }
 
-        // 發送請求
-        self.send_upload_request(form).await
+    /// 上傳記憶體中的內容，並通過 options 回報進度或取消上傳
+    pub async fn upload_bytes_with(
+        &self,
+        data: impl Into<Bytes>,
+        file_name: &str,
+        mime_type: Option<&str>,
+        options: &UploadOptions,
+    ) -> Result<FileUploadResponse, PoeError> {
+        options.check_cancelled()?;
+        let data = data.into();
+        #[cfg(feature = "trace")]
+        debug!(
+            "開始上傳記憶體內容: {} | 大小: {} 字節 | MIME 類型: {:?}",
+            file_name,
+            data.len(),
+            mime_type
+        );
+
+        self.upload_policy
+            .check_file_size(file_name, data.len() as u64)?;
+        let content_type = match mime_type {
+            Some(mime_type) => mime_type.to_string(),
+            None => detect_mime_type(Path::new(file_name), &data[..data.len().min(SNIFF_LEN)])
+                .to_string(),
+        };
+        self.upload_policy.check_mime_type(&content_type)?;
+
+        let len = data.len() as u64;
+        let cache_key = self
+            .upload_cache
+            .as_ref()
+            .map(|_| UploadCache::content_key(&data));
+        self.cached_upload(
+            cache_key,
+            Some(len),
+            options,
+            self.send_reader_upload(
+                std::io::Cursor::new(data),
+                file_name,
+                &content_type,
+                Some(len),
+                options,
+            ),
+        )
+        .await
+    }
+
+    /// 從任意非同步讀取來源上傳
+    ///
+    /// 提供 len 時會在上傳前檢查大小限制，上傳過程中也會按實際送出的位元組檢查；
+    /// 未指定 mime_type 時會讀取開頭內容自動檢測。
+    /// 讀取來源只能讀取一次，因此不使用上傳快取。
+    pub async fn upload_reader(
+        &self,
+        reader: impl AsyncRead + Send + 'static,
+        file_name: &str,
+        mime_type: Option<&str>,
+        len: Option<u64>,
+    ) -> Result<FileUploadResponse, PoeError> {
+        self.upload_reader_with(reader, file_name, mime_type, len, &UploadOptions::default())
+            .await
+    }
+
+    /// 從任意非同步讀取來源上傳，並通過 options 回報進度或取消上傳
+    pub async fn upload_reader_with(
+        &self,
+        reader: impl AsyncRead + Send + 'static,
+        file_name: &str,
+        mime_type: Option<&str>,
+        len: Option<u64>,
+        options: &UploadOptions,
+    ) -> Result<FileUploadResponse, PoeError> {
+        options.check_cancelled()?;
+        #[cfg(feature = "trace")]
+        debug!(
+            "開始從讀取來源上傳: {} | 長度: {:?} | MIME 類型: {:?}",
+            file_name, len, mime_type
+        );
+
+        if let Some(len) = len {
+            self.upload_policy.check_file_size(file_name, len)?;
+        }
+
+        let mut reader = UploadReader::new(reader);
+        let content_type = match mime_type {
+            Some(mime_type) => mime_type.to_string(),
+            None => {
+                // 讀取開頭用於檢測，之後再接回剩餘內容
+                let mut head = Vec::with_capacity(SNIFF_LEN);
+                options
+                    .run(async {
+                        (&mut reader)
+                            .take(SNIFF_LEN as u64)
+                            .read_to_end(&mut head)
+                            .await?;
+                        Ok(())
+                    })
+                    .await?;
+                let content_type = detect_mime_type(Path::new(file_name), &head);
+                reader = UploadReader::new(std::io::Cursor::new(head).chain(reader));
+                content_type.to_string()
+            }
+        };
+        self.upload_policy.check_mime_type(&content_type)?;
+
+        self.send_reader_upload(reader, file_name, &content_type, len, options)
+            .await
     }
 
     /// 上傳遠端檔案 (通過URL)
     pub async fn upload_remote_file(
         &self,
         download_url: &str,
+    ) -> Result<FileUploadResponse, PoeError> {
+        self.upload_remote_file_with(download_url, &UploadOptions::default())
+            .await
+    }
+
+    /// 上傳遠端檔案，並通過 options 取消上傳（遠端檔案由伺服器下載，不回報進度）
+    pub async fn upload_remote_file_with(
+        &self,
+        download_url: &str,
+        options: &UploadOptions,
     ) -> Result<FileUploadResponse, PoeError> {
         #[cfg(feature = "trace")]
         debug!("開始上傳遠端檔案: {}", download_url);
@@ -860,76 +506,399 @@ impl PoeClient {
         let form = reqwest::multipart::Form::new().text("download_url", download_url.to_string());
 
         // 發送請求
-        self.send_upload_request(form).await
+        let cache_key = self
+            .upload_cache
+            .as_ref()
+            .map(|_| UploadCache::url_key(download_url));
+        self.cached_upload(
+            cache_key,
+            Some(0),
+            options,
+            options.run(self.send_upload_request(form)),
+        )
+        .await
+    }
+
+    // 查詢上傳快取，未命中時執行上傳並記錄結果 (內部方法)
+    async fn cached_upload(
+        &self,
+        cache_key: Option<String>,
+        len: Option<u64>,
+        options: &UploadOptions,
+        upload: impl Future<Output = Result<FileUploadResponse, PoeError>>,
+    ) -> Result<FileUploadResponse, PoeError> {
+        let cache = self.upload_cache.as_ref().zip(cache_key);
+        if let Some((cache, key)) = &cache
+            && let Some(response) = cache.get(key)
+        {
+            // 命中時不發送內容，直接回報完成
+            if let Some(len) = len {
+                options.report(UploadProgress {
+                    sent: len,
+                    total: Some(len),
+                });
+            }
+            return Ok(response);
+        }
+
+        let response = upload.await?;
+        if let Some((cache, key)) = &cache {
+            cache.insert(key, &response);
+        }
+        Ok(response)
     }
 
     /// 批量上傳檔案 (接受混合的本地和遠端檔案)
-    pub async fn upload_files_batch(
+    ///
+    /// 每個檔案的結果按輸入順序返回，單個檔案失敗不影響其他檔案。
+    pub async fn upload_files_batch(&self, files: Vec<FileUploadRequest>) -> Vec<BatchUploadItem> {
+        self.upload_files_batch_with(files, &UploadOptions::default())
+            .await
+    }
+
+    /// 批量上傳檔案，按 options 限制並發數及重試失敗的項目
+    ///
+    /// 進度回調收到的是所有檔案合計的進度，取消時中斷全部上傳。
+    pub async fn upload_files_batch_with(
         &self,
         files: Vec<FileUploadRequest>,
-    ) -> Result<Vec<FileUploadResponse>, PoeError> {
+        options: &UploadOptions,
+    ) -> Vec<BatchUploadItem> {
         #[cfg(feature = "trace")]
-        debug!("開始批量上傳檔案，數量: {}", files.len());
+        debug!(
+            "開始批量上傳檔案，數量: {} | 並發數: {} | 重試次數: {}",
+            files.len(),
+            options.concurrency(),
+            options.retries()
+        );
 
-        if files.is_empty() {
-            return Ok(Vec::new());
+        // 預先取得各檔案大小以計算總進度，遠端檔案不經本機傳送
+        let mut totals = Vec::with_capacity(files.len());
+        if options.has_progress() {
+            for file_request in &files {
+                let total = match file_request {
+                    FileUploadRequest::LocalFile { file, .. } => tokio::fs::metadata(file)
+                        .await
+                        .ok()
+                        .map(|metadata| metadata.len()),
+                    FileUploadRequest::RemoteFile { .. } => Some(0),
+                    FileUploadRequest::Bytes { data, .. } => Some(data.len() as u64),
+                    FileUploadRequest::Reader { len, .. } => *len,
+                };
+                totals.push(total);
+            }
         }
+        let batch_progress = Arc::new(BatchProgress::new(totals));
 
-        // 為每個檔案創建上傳任務
-        let mut upload_tasks = Vec::with_capacity(files.len());
-
-        for file_request in files {
-            let task = match file_request {
-                FileUploadRequest::LocalFile { file, mime_type } => {
-                    let client = self.clone();
-                    let file_path = file.clone();
-                    tokio::spawn(async move {
-                        client
-                            .upload_local_file(&file_path, mime_type.as_deref())
-                            .await
-                    })
-                }
-                FileUploadRequest::RemoteFile { download_url } => {
-                    let client = self.clone();
-                    let url = download_url.clone();
-                    tokio::spawn(async move { client.upload_remote_file(&url).await })
+        // buffered 保持輸入順序，同時最多進行 concurrency 個上傳
+        let items: Vec<BatchUploadItem> = futures_util::stream::iter(files.into_iter().enumerate())
+            .map(|(index, file_request)| {
+                let options = options.for_batch_item(index, &batch_progress);
+                async move { self.upload_batch_item(file_request, &options).await }
+            })
+            .buffered(options.concurrency())
+            .collect()
+            .await;
+
+        #[cfg(feature = "trace")]
+        debug!(
+            "批量上傳完成，成功 {} 個，失敗 {} 個",
+            items.iter().filter(|item| item.is_ok()).count(),
+            items.iter().filter(|item| !item.is_ok()).count()
+        );
+
+        items
+    }
+
+    // 上傳批量中的單個檔案，可重試的錯誤按 options 重試
+    async fn upload_batch_item(
+        &self,
+        file_request: FileUploadRequest,
+        options: &UploadOptions,
+    ) -> BatchUploadItem {
+        let request = file_request.try_clone();
+        let mut result = self.upload_request(file_request, options).await;
+
+        let mut attempt = 0;
+        while let Err(error) = &result
+            && is_retryable(error)
+            && attempt < options.retries()
+            && let Some(retry_request) = request.as_ref().and_then(FileUploadRequest::try_clone)
+        {
+            attempt += 1;
+            #[cfg(feature = "trace")]
+            warn!("檔案上傳失敗，第 {} 次重試: {}", attempt, error);
+            let delay = options.retry_delay(attempt);
+            if let Err(e) = options
+                .run(async {
+                    tokio::time::sleep(delay).await;
+                    Ok(())
+                })
+                .await
+            {
+                result = Err(e);
+                break;
+            }
+            result = self.upload_request(retry_request, options).await;
+        }
+
+        #[cfg(feature = "trace")]
+        match &result {
+            Ok(response) => debug!("檔案上傳成功: {}", response.attachment_url),
+            Err(e) => warn!("檔案上傳失敗: {}", e),
+        }
+
+        BatchUploadItem { request, result }
+    }
+
+    // 根據請求類型選擇上傳方式 (內部方法)
+    async fn upload_request(
+        &self,
+        file_request: FileUploadRequest,
+        options: &UploadOptions,
+    ) -> Result<FileUploadResponse, PoeError> {
+        match file_request {
+            FileUploadRequest::LocalFile { file, mime_type } => {
+                self.upload_local_file_with(&file, mime_type.as_deref(), options)
+                    .await
+            }
+            FileUploadRequest::RemoteFile { download_url } => {
+                self.upload_remote_file_with(&download_url, options).await
+            }
+            FileUploadRequest::Bytes {
+                data,
+                file_name,
+                mime_type,
+            } => {
+                self.upload_bytes_with(data, &file_name, mime_type.as_deref(), options)
+                    .await
+            }
+            FileUploadRequest::Reader {
+                reader,
+                file_name,
+                mime_type,
+                len,
+            } => {
+                self.upload_reader_with(reader, &file_name, mime_type.as_deref(), len, options)
+                    .await
+            }
+        }
+    }
+
+    /// 批量上傳本地檔案或 http(s) 地址並轉換為附件，附帶檔名及內容類型
+    ///
+    /// 附件按輸入順序返回，任一檔案失敗時返回第一個錯誤。
+    pub async fn prepare_attachments(
+        &self,
+        sources: impl IntoIterator<Item = impl Into<FileUploadRequest>>,
+    ) -> Result<Vec<Attachment>, PoeError> {
+        self.prepare_attachments_with(sources, &UploadOptions::default())
+            .await
+    }
+
+    /// 批量上傳並轉換為附件，按 options 回報進度、限制並發數及重試
+    pub async fn prepare_attachments_with(
+        &self,
+        sources: impl IntoIterator<Item = impl Into<FileUploadRequest>>,
+        options: &UploadOptions,
+    ) -> Result<Vec<Attachment>, PoeError> {
+        let requests: Vec<FileUploadRequest> = sources.into_iter().map(Into::into).collect();
+        let names: Vec<Option<String>> =
+            requests.iter().map(FileUploadRequest::file_name).collect();
+
+        let items = self.upload_files_batch_with(requests, options).await;
+        items
+            .into_iter()
+            .zip(names)
+            .map(|(item, name)| {
+                let response = item.into_result()?;
+                #[cfg(feature = "trace")]
+                debug!("附件上傳成功: {:?} -> {}", name, response.attachment_url);
+                Ok(Attachment {
+                    name,
+                    ..Attachment::from(response)
+                })
+            })
+            .collect()
+    }
+
+    /// 批量上傳本地檔案或 http(s) 地址並作為附件添加到消息，任一檔案失敗時返回錯誤
+    pub async fn attach_files(
+        &self,
+        mut message: ChatMessage,
+        sources: impl IntoIterator<Item = impl Into<FileUploadRequest>>,
+    ) -> Result<ChatMessage, PoeError> {
+        for attachment in self.prepare_attachments(sources).await? {
+            message = message.with_attachment(attachment);
+        }
+        Ok(message)
+    }
+
+    /// 下載 File 事件中的檔案，超過大小上限時中止下載
+    pub async fn download_file(&self, file: &FileData) -> Result<Bytes, PoeError> {
+        let response = self.open_download(file).await?;
+        // Content-Length 由伺服器提供，預先分配的容量需設上限
+        let capacity = response
+            .content_length()
+            .unwrap_or(0)
+            .min(self.max_download_size.unwrap_or(u64::MAX))
+            .min(MAX_DOWNLOAD_PREALLOCATION);
+        let mut data = Vec::with_capacity(capacity as usize);
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk?);
+            self.check_download_size(file, data.len() as u64)?;
+        }
+
+        #[cfg(feature = "trace")]
+        debug!("檔案下載完成: {} | 大小: {} 字節", file.name, data.len());
+        Ok(Bytes::from(data))
+    }
+
+    /// 以串流方式將 File 事件中的檔案保存到目錄，返回保存的路徑
+    ///
+    /// 檔名取自 FileData 並去除路徑部分，已有同名檔案時加上編號而不覆蓋；
+    /// 下載失敗或超過大小上限時會刪除未完成的檔案。
+    pub async fn save_file_to(
+        &self,
+        file: &FileData,
+        dir: impl AsRef<Path>,
+    ) -> Result<PathBuf, PoeError> {
+        let dir = dir.as_ref();
+        let response = self.open_download(file).await?;
+        tokio::fs::create_dir_all(dir).await?;
+        let (path, mut output) = create_unique_file(dir, &safe_file_name(&file.name)).await?;
+
+        let result: Result<(), PoeError> = async {
+            let mut received = 0u64;
+            let mut stream = response.bytes_stream();
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                received += chunk.len() as u64;
+                self.check_download_size(file, received)?;
+                output.write_all(&chunk).await?;
+            }
+            output.flush().await?;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            #[cfg(feature = "trace")]
+            warn!("保存檔案失敗，刪除未完成的檔案: {} | {}", path.display(), e);
+            drop(output);
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(e);
+        }
+
+        #[cfg(feature = "trace")]
+        debug!("檔案已保存: {} -> {}", file.url, path.display());
+        Ok(path)
+    }
+
+    /// 下載 bot 產生的檔案，並將回覆 markdown 中的內聯引用改寫為本地路徑或 data URI
+    pub async fn resolve_inline_files(
+        &self,
+        markdown: &str,
+        files: &[FileData],
+        target: InlineTarget<'_>,
+    ) -> Result<String, PoeError> {
+        let mut targets = HashMap::new();
+        for file in files.iter().filter(|file| !file.inline_ref.is_empty()) {
+            let location = match target {
+                InlineTarget::Directory(dir) => self
+                    .save_file_to(file, dir)
+                    .await?
+                    .to_string_lossy()
+                    .into_owned(),
+                InlineTarget::DataUri => {
+                    data_uri(&file.content_type, &self.download_file(file).await?)
                 }
             };
-            upload_tasks.push(task);
+            targets.insert(file.inline_ref.clone(), location);
         }
+        Ok(rewrite_inline_refs(markdown, &targets))
+    }
 
-        // 等待所有上傳任務完成
-        let results = join_all(upload_tasks).await;
+    // 發送下載請求並檢查狀態及宣告的長度 (內部方法)
+    async fn open_download(&self, file: &FileData) -> Result<reqwest::Response, PoeError> {
+        #[cfg(feature = "trace")]
+        debug!("開始下載檔案: {} | {}", file.name, file.url);
 
-        // 收集結果
-        let mut upload_responses = Vec::with_capacity(results.len());
+        // 檔案地址不屬於 Poe API，不附帶 access key
+        let response = self.client.get(&file.url).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            #[cfg(feature = "trace")]
+            warn!("檔案下載失敗 - 狀態碼: {} | {}", status, file.url);
+            return Err(PoeError::FileDownloadFailed(format!(
+                "{} - 狀態碼: {}",
+                file.url, status
+            )));
+        }
+        if let Some(len) = response.content_length() {
+            self.check_download_size(file, len)?;
+        }
+        Ok(response)
+    }
 
-        for task_result in results.into_iter() {
-            match task_result {
-                Ok(upload_result) => match upload_result {
-                    Ok(response) => {
-                        #[cfg(feature = "trace")]
-                        debug!("檔案上傳成功: {}", response.attachment_url);
-                        upload_responses.push(response);
-                    }
-                    Err(e) => {
-                        #[cfg(feature = "trace")]
-                        warn!("檔案上傳失敗: {}", e);
-                        return Err(e);
-                    }
-                },
-                Err(e) => {
-                    #[cfg(feature = "trace")]
-                    warn!("檔案上傳任務失敗: {}", e);
-                    return Err(PoeError::FileUploadFailed(format!("上傳任務失敗: {}", e)));
-                }
+    fn check_download_size(&self, file: &FileData, size: u64) -> Result<(), PoeError> {
+        match self.max_download_size {
+            Some(max) if size > max => {
+                #[cfg(feature = "trace")]
+                warn!("下載檔案超過大小上限: {} | {} > {}", file.name, size, max);
+                Err(PoeError::FileTooLarge(format!(
+                    "{} 大小超過下載上限 {} 字節",
+                    file.name, max
+                )))
             }
+            _ => Ok(()),
         }
+    }
 
-        #[cfg(feature = "trace")]
-        debug!("批量上傳全部成功，共 {} 個檔案", upload_responses.len());
+    /// 以串流方式上傳讀取來源的內容 (內部方法)
+    async fn send_reader_upload(
+        &self,
+        reader: impl AsyncRead + Send + 'static,
+        file_name: &str,
+        content_type: &str,
+        len: Option<u64>,
+        options: &UploadOptions,
+    ) -> Result<FileUploadResponse, PoeError> {
+        // 按實際送出的內容統計進度，並確認長度與宣告的一致，避免送出格式錯誤的 multipart 內容
+        let stream = CountingStream::new(
+            ReaderStream::new(UploadReader::new(reader)),
+            file_name,
+            len,
+            &self.upload_policy,
+            options,
+        );
+        let stream_error = stream.error_slot();
+        let body = reqwest::Body::wrap_stream(stream);
+        let file_part = match len {
+            Some(len) => reqwest::multipart::Part::stream_with_length(body, len),
+            None => reqwest::multipart::Part::stream(body),
+        }
+        .file_name(file_name.to_string())
+        .mime_str(content_type)
+        .map_err(|e| {
+            #[cfg(feature = "trace")]
+            warn!("設置 MIME 類型失敗: {}", e);
+            PoeError::FileUploadFailed(format!("設置 MIME 類型失敗: {}", e))
+        })?;
+
+        let form = reqwest::multipart::Form::new().part("file", file_part);
 
-        Ok(upload_responses)
+        // 發送請求，取消時中斷連接；內容檢查失敗時返回記錄的錯誤而非籠統的請求錯誤
+        let result = options.run(self.send_upload_request(form)).await;
+        match stream_error
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+        {
+            Some(error) if result.is_err() => Err(error),
+            _ => result,
+        }
     }
 
     /// 發送檔案上傳請求 (內部方法)
@@ -994,6 +963,129 @@ impl PoeClient {
         Ok(upload_response)
     }
 
+    /// 調用 OpenAI 相容的 v1/chat/completions API (非串流)
+    pub async fn chat_completions(
+        &self,
+        mut request: ChatCompletionRequest,
+    ) -> Result<ChatCompletion, PoeError> {
+        #[cfg(feature = "trace")]
+        debug!("開始 v1/chat/completions 請求，model: {}", request.model);
+
+        request.stream = Some(false);
+        request.stream_options = None;
+        let response = self.send_chat_completions(&request).await?;
+
+        let response_text = response.text().await?;
+        #[cfg(feature = "trace")]
+        debug!("v1/chat/completions 回應內容: {}", response_text);
+
+        serde_json::from_str(&response_text).map_err(|e| {
+            #[cfg(feature = "trace")]
+            warn!("解析 v1/chat/completions 回應失敗: {}", e);
+            PoeError::JsonParseFailed(e)
+        })
+    }
+
+    /// 調用 OpenAI 相容的 v1/chat/completions API 並串流返回 chat.completion.chunk
+    ///
+    /// 未指定 stream_options 時會自動要求在最後一個串流塊中返回用量
+    pub async fn stream_chat_completions(
+        &self,
+        mut request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<ChatCompletionChunk, PoeError>> + Send>>, PoeError>
+    {
+        #[cfg(feature = "trace")]
+        debug!(
+            "開始 v1/chat/completions 串流請求，model: {}",
+            request.model
+        );
+
+        request.stream = Some(true);
+        if request.stream_options.is_none() {
+            request.stream_options = Some(StreamOptions {
+                include_usage: true,
+            });
+        }
+        let response = self.send_chat_completions(&request).await?;
+
+        let mut line_buffer: Vec<u8> = Vec::new();
+        let stream = response
+            .bytes_stream()
+            .map(move |result| match result {
+                Ok(chunk) => {
+                    if let Err(e) = check_pending_line(line_buffer.len(), &chunk) {
+                        line_buffer.clear();
+                        return vec![Err(e)];
+                    }
+                    line_buffer.extend_from_slice(&chunk);
+                    let mut chunks = Vec::new();
+                    // 只處理完整的行，不完整的行留待下一個塊
+                    while let Some(pos) = line_buffer.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = line_buffer.drain(..=pos).collect();
+                        if let Some(parsed) = parse_completion_chunk_line(&line) {
+                            chunks.push(parsed);
+                        }
+                    }
+                    chunks
+                }
+                Err(e) => {
+                    #[cfg(feature = "trace")]
+                    warn!("v1/chat/completions 串流錯誤: {}", e);
+                    vec![Err(PoeError::RequestFailed(e))]
+                }
+            })
+            .flat_map(futures_util::stream::iter)
+            // 單行超過上限後結束串流，其餘解析錯誤不影響後續的塊
+            .scan(false, |overflowed, result| {
+                if *overflowed {
+                    return futures_util::future::ready(None);
+                }
+                *overflowed = matches!(result, Err(PoeError::EventParseFailed(_)));
+                futures_util::future::ready(Some(result))
+            });
+
+        Ok(Box::pin(stream))
+    }
+
+    // 發送 v1/chat/completions 請求並檢查狀態碼
+    async fn send_chat_completions(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<reqwest::Response, PoeError> {
+        let url = format!("{}/v1/chat/completions", self.poe_base_url);
+        #[cfg(feature = "trace")]
+        debug!("發送 v1/chat/completions 請求至 URL: {}", url);
+
+        let response = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.access_key))
+            .json(request)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "無法讀取回應內容".to_string());
+
+            #[cfg(feature = "trace")]
+            warn!(
+                "v1/chat/completions API 回應錯誤 - 狀態碼: {}, 內容: {}",
+                status, text
+            );
+
+            return Err(PoeError::BotError(format!(
+                "v1/chat/completions API 回應錯誤 - 狀態碼: {}, 內容: {}",
+                status, text
+            )));
+        }
+
+        Ok(response)
+    }
+
     /// 獲取 v1/models API 的模型列表 (需要 access_key)
     pub async fn get_v1_model_list(&self) -> Result<ModelResponse, PoeError> {
         #[cfg(feature = "trace")]
@@ -1099,10 +1191,11 @@ impl PoeClient {
     pub fn remove_xml_tool_calls(text: &str) -> String {
         // 創建一個臨時的 ChatMessage 來檢測工具調用
         let message = ChatMessage {
-            role: "assistant".to_string(),
+            role: Role::Bot,
             content: text.to_string(),
             attachments: None,
-            content_type: "text/plain".to_string(),
+            content_type: ContentType::Plain,
+            ..Default::default()
         };
 
         // 如果沒有檢測到工具調用，直接返回原文本
@@ -1163,7 +1256,43 @@ impl PoeClient {
     }
 }
 
+// 解析 v1/chat/completions 串流中的一行，非 data 行及 [DONE] 返回 None
+fn parse_completion_chunk_line(line: &[u8]) -> Option<Result<ChatCompletionChunk, PoeError>> {
+    let line = String::from_utf8_lossy(line);
+    let data = line.trim().strip_prefix("data:")?.trim();
+    if data.is_empty() || data == "[DONE]" {
+        return None;
+    }
+
+    let value: Value = match serde_json::from_str(data) {
+        Ok(value) => value,
+        Err(e) => return Some(Err(PoeError::JsonParseFailed(e))),
+    };
+
+    // 串流中途返回的錯誤對象
+    if let Some(error) = value.get("error") {
+        let message = error
+            .get("message")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| error.to_string());
+        #[cfg(feature = "trace")]
+        warn!("v1/chat/completions 串流返回錯誤: {}", message);
+        return Some(Err(PoeError::BotError(message)));
+    }
+
+    Some(serde_json::from_value(value).map_err(PoeError::JsonParseFailed))
+}
+
 pub async fn get_model_list(language_code: Option<&str>) -> Result<ModelResponse, PoeError> {
+    get_model_list_from(DEFAULT_POE_GQL_URL, language_code).await
+}
+
+/// 從指定的 GraphQL 端點獲取模型列表，用於測試或自訂代理
+pub async fn get_model_list_from(
+    gql_url: &str,
+    language_code: Option<&str>,
+) -> Result<ModelResponse, PoeError> {
     #[cfg(feature = "trace")]
     debug!("開始獲取模型列表，語言代碼: {:?}", language_code);
 
@@ -1224,10 +1353,10 @@ pub async fn get_model_list(language_code: Option<&str>) -> Result<ModelResponse
     }
 
     #[cfg(feature = "trace")]
-    debug!("發送 GraphQL 請求至 {}", POE_GQL_URL);
+    debug!("發送 GraphQL 請求至 {}", gql_url);
 
     let response = client
-        .post(POE_GQL_URL)
+        .post(gql_url)
         .headers(headers)
         .json(&payload)
         .send()
@@ -0,0 +1,409 @@
+use crate::error::PoeError;
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+#[cfg(feature = "trace")]
+use tracing::{debug, warn};
+
+// 對話記錄，按發生順序保存消息及工具回合
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Conversation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot_name: Option<String>,
+    #[serde(default)]
+    pub user_id: String,
+    #[serde(default)]
+    pub conversation_id: String,
+    #[serde(default)]
+    pub message_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ChatTool>>,
+    #[serde(default)]
+    pub entries: Vec<ConversationEntry>,
+}
+
+// 對話中的一個條目：消息或一輪工具調用
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+pub enum ConversationEntry {
+    Message(ChatMessage),
+    ToolRound(ToolRound),
+}
+
+// 一輪工具調用及其結果
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ToolRound {
+    #[serde(default)]
+    pub tool_calls: Vec<ChatToolCall>,
+    #[serde(default)]
+    pub tool_results: Vec<ChatToolResult>,
+}
+
+// JSONL 格式的單行記錄
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum ConversationRecord {
+    Header {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bot_name: Option<String>,
+        #[serde(default)]
+        user_id: String,
+        #[serde(default)]
+        conversation_id: String,
+        #[serde(default)]
+        message_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tools: Option<Vec<ChatTool>>,
+    },
+    Message(ChatMessage),
+    ToolCalls {
+        tool_calls: Vec<ChatToolCall>,
+    },
+    ToolResults {
+        tool_results: Vec<ChatToolResult>,
+    },
+}
+
+impl Conversation {
+    pub fn new(bot_name: &str) -> Self {
+        Self {
+            bot_name: Some(bot_name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// 從現有請求建立對話記錄，請求中的工具調用記錄為最後一輪工具回合
+    pub fn from_request(request: &ChatRequest) -> Self {
+        let mut conversation = Self {
+            bot_name: None,
+            user_id: request.user_id.clone(),
+            conversation_id: request.conversation_id.clone(),
+            message_id: request.message_id.clone(),
+            tools: request.tools.clone(),
+            entries: request
+                .query
+                .iter()
+                .cloned()
+                .map(ConversationEntry::Message)
+                .collect(),
+        };
+        if request.tool_calls.is_some() || request.tool_results.is_some() {
+            conversation.record_tool_round(
+                request.tool_calls.clone().unwrap_or_default(),
+                request.tool_results.clone().unwrap_or_default(),
+            );
+        }
+        conversation
+    }
+
+    /// 按順序遍歷所有消息
+    pub fn messages(&self) -> impl Iterator<Item = &ChatMessage> {
+        self.entries.iter().filter_map(|entry| match entry {
+            ConversationEntry::Message(message) => Some(message),
+            ConversationEntry::ToolRound(_) => None,
+        })
+    }
+
+    /// 按順序遍歷所有工具回合
+    pub fn tool_rounds(&self) -> impl Iterator<Item = &ToolRound> {
+        self.entries.iter().filter_map(|entry| match entry {
+            ConversationEntry::ToolRound(round) => Some(round),
+            ConversationEntry::Message(_) => None,
+        })
+    }
+
+    /// 添加一條消息
+    pub fn push_message(&mut self, message: ChatMessage) {
+        self.entries.push(ConversationEntry::Message(message));
+    }
+
+    /// 將 bot 的完整回覆添加為消息
+    pub fn push_bot_reply(&mut self, content: &str) {
+        self.push_message(ChatMessage::bot(content));
+    }
+
+    /// 移除最後一條消息及其後的工具回合
+    pub fn pop_message(&mut self) -> Option<ChatMessage> {
+        while let Some(entry) = self.entries.pop() {
+            if let ConversationEntry::Message(message) = entry {
+                return Some(message);
+            }
+        }
+        None
+    }
+
+    /// 在當前位置記錄一輪工具調用及其結果
+    pub fn record_tool_round(
+        &mut self,
+        tool_calls: Vec<ChatToolCall>,
+        tool_results: Vec<ChatToolResult>,
+    ) {
+        self.entries.push(ConversationEntry::ToolRound(ToolRound {
+            tool_calls,
+            tool_results,
+        }));
+    }
+
+    /// 重建可繼續對話的請求
+    ///
+    /// Poe 請求只能攜帶一輪工具調用，因此只有位於末尾的工具回合會放入請求。
+    pub fn to_request(&self) -> ChatRequest {
+        let trailing_round = match self.entries.last() {
+            Some(ConversationEntry::ToolRound(round)) => Some(round),
+            _ => None,
+        };
+        ChatRequest {
+            query: self.messages().cloned().collect(),
+            user_id: self.user_id.clone(),
+            conversation_id: self.conversation_id.clone(),
+            message_id: self.message_id.clone(),
+            tools: self.tools.clone(),
+            tool_calls: trailing_round.map(|round| round.tool_calls.clone()),
+            tool_results: trailing_round.map(|round| round.tool_results.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// 轉換為 JSONL 記錄
+    pub fn to_records(&self) -> Vec<ConversationRecord> {
+        let mut records = Vec::with_capacity(self.entries.len() * 2 + 1);
+        records.push(ConversationRecord::Header {
+            bot_name: self.bot_name.clone(),
+            user_id: self.user_id.clone(),
+            conversation_id: self.conversation_id.clone(),
+            message_id: self.message_id.clone(),
+            tools: self.tools.clone(),
+        });
+        for entry in &self.entries {
+            match entry {
+                ConversationEntry::Message(message) => {
+                    records.push(ConversationRecord::Message(message.clone()))
+                }
+                ConversationEntry::ToolRound(round) => {
+                    records.push(ConversationRecord::ToolCalls {
+                        tool_calls: round.tool_calls.clone(),
+                    });
+                    records.push(ConversationRecord::ToolResults {
+                        tool_results: round.tool_results.clone(),
+                    });
+                }
+            }
+        }
+        records
+    }
+
+    /// 從 JSONL 記錄重建對話
+    ///
+    /// tool_calls 記錄開始新的工具回合，緊隨其後的 tool_results 記錄歸入同一回合。
+    pub fn from_records(records: impl IntoIterator<Item = ConversationRecord>) -> Self {
+        let mut conversation = Self::default();
+        for record in records {
+            match record {
+                ConversationRecord::Header {
+                    bot_name,
+                    user_id,
+                    conversation_id,
+                    message_id,
+                    tools,
+                } => {
+                    conversation.bot_name = bot_name;
+                    conversation.user_id = user_id;
+                    conversation.conversation_id = conversation_id;
+                    conversation.message_id = message_id;
+                    conversation.tools = tools;
+                }
+                ConversationRecord::Message(message) => conversation.push_message(message),
+                ConversationRecord::ToolCalls { tool_calls } => {
+                    conversation.record_tool_round(tool_calls, Vec::new())
+                }
+                ConversationRecord::ToolResults { tool_results } => {
+                    match conversation.entries.last_mut() {
+                        Some(ConversationEntry::ToolRound(round))
+                            if round.tool_results.is_empty() =>
+                        {
+                            round.tool_results = tool_results
+                        }
+                        _ => conversation.record_tool_round(Vec::new(), tool_results),
+                    }
+                }
+            }
+        }
+        conversation
+    }
+
+    /// 序列化為 JSONL 文本
+    pub fn to_jsonl(&self) -> Result<String, PoeError> {
+        let mut output = String::new();
+        for record in self.to_records() {
+            output.push_str(&serde_json::to_string(&record)?);
+            output.push('\n');
+        }
+        Ok(output)
+    }
+
+    /// 從 JSONL 文本解析對話，忽略空行
+    pub fn from_jsonl(text: &str) -> Result<Self, PoeError> {
+        let records = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str::<ConversationRecord>)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_records(records))
+    }
+
+    /// 儲存為 JSON 檔案
+    pub async fn save_json(&self, path: impl AsRef<Path>) -> Result<(), PoeError> {
+        let path = path.as_ref();
+        #[cfg(feature = "trace")]
+        debug!("儲存對話為 JSON: {}", path.display());
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(path, json).await?;
+        Ok(())
+    }
+
+    /// 從 JSON 檔案載入
+    pub async fn load_json(path: impl AsRef<Path>) -> Result<Self, PoeError> {
+        let text = read_conversation_file(path.as_ref()).await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// 儲存為 JSONL 檔案（覆蓋現有內容）
+    pub async fn save_jsonl(&self, path: impl AsRef<Path>) -> Result<(), PoeError> {
+        let path = path.as_ref();
+        #[cfg(feature = "trace")]
+        debug!("儲存對話為 JSONL: {}", path.display());
+        tokio::fs::write(path, self.to_jsonl()?).await?;
+        Ok(())
+    }
+
+    /// 從 JSONL 檔案載入
+    pub async fn load_jsonl(path: impl AsRef<Path>) -> Result<Self, PoeError> {
+        let text = read_conversation_file(path.as_ref()).await?;
+        Self::from_jsonl(&text)
+    }
+
+    /// 將單條記錄附加到 JSONL 檔案末尾，適用於審計日誌
+    pub async fn append_jsonl(
+        path: impl AsRef<Path>,
+        record: &ConversationRecord,
+    ) -> Result<(), PoeError> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// 匯出為可讀的 Markdown 對話記錄
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        match self.bot_name {
+            Some(ref bot_name) => md.push_str(&format!("# 與 {} 的對話\n", bot_name)),
+            None => md.push_str("# 對話記錄\n"),
+        }
+        if !self.conversation_id.is_empty() {
+            md.push_str(&format!("\n- 對話 ID: `{}`\n", self.conversation_id));
+        }
+
+        for entry in &self.entries {
+            let message = match entry {
+                ConversationEntry::Message(message) => message,
+                ConversationEntry::ToolRound(round) => {
+                    push_tool_round_markdown(&mut md, round);
+                    continue;
+                }
+            };
+            md.push_str(&format!("\n## {}\n\n", role_heading(&message.role)));
+            md.push_str(message.content.trim_end());
+            md.push('\n');
+
+            if let Some(ref attachments) = message.attachments
+                && !attachments.is_empty()
+            {
+                md.push_str("\n**附件:**\n\n");
+                for attachment in attachments {
+                    match attachment.content_type {
+                        Some(ref content_type) => {
+                            md.push_str(&format!("- <{}> ({})\n", attachment.url, content_type))
+                        }
+                        None => md.push_str(&format!("- <{}>\n", attachment.url)),
+                    }
+                }
+            }
+        }
+
+        md
+    }
+
+    /// 匯出 Markdown 對話記錄到檔案
+    pub async fn export_markdown(&self, path: impl AsRef<Path>) -> Result<(), PoeError> {
+        tokio::fs::write(path.as_ref(), self.to_markdown()).await?;
+        Ok(())
+    }
+}
+
+impl From<&ChatRequest> for Conversation {
+    fn from(request: &ChatRequest) -> Self {
+        Self::from_request(request)
+    }
+}
+
+// 讀取對話檔案，檔案不存在時返回 FileNotFound
+async fn read_conversation_file(path: &Path) -> Result<String, PoeError> {
+    if !path.exists() {
+        #[cfg(feature = "trace")]
+        warn!("對話檔案不存在: {}", path.display());
+        return Err(PoeError::FileNotFound(path.display().to_string()));
+    }
+    Ok(tokio::fs::read_to_string(path).await?)
+}
+
+// 以 Markdown 輸出一輪工具調用及其結果
+fn push_tool_round_markdown(md: &mut String, round: &ToolRound) {
+    if !round.tool_calls.is_empty() {
+        md.push_str("\n## 工具調用\n");
+        for tool_call in &round.tool_calls {
+            md.push_str(&format!(
+                "\n### `{}` ({})\n\n```json\n{}\n```\n",
+                tool_call.function.name,
+                tool_call.id,
+                pretty_arguments(&tool_call.function.arguments)
+            ));
+        }
+    }
+
+    if !round.tool_results.is_empty() {
+        md.push_str("\n## 工具結果\n");
+        for tool_result in &round.tool_results {
+            md.push_str(&format!(
+                "\n### `{}` ({})\n\n```\n{}\n```\n",
+                tool_result.name, tool_result.tool_call_id, tool_result.content
+            ));
+        }
+    }
+}
+
+// Markdown 中顯示的角色標題
+fn role_heading(role: &Role) -> &str {
+    match role {
+        Role::User => "用戶",
+        Role::Bot => "Bot",
+        Role::System => "系統",
+        Role::Tool => "工具",
+        Role::Other(role) => role,
+    }
+}
+
+// 若工具參數為有效 JSON 則格式化輸出
+fn pretty_arguments(arguments: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(arguments)
+        .ok()
+        .and_then(|value| serde_json::to_string_pretty(&value).ok())
+        .unwrap_or_else(|| arguments.to_string())
+}
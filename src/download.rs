@@ -0,0 +1,128 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// 檔名衝突時嘗試的最大編號
+const MAX_NAME_ATTEMPTS: usize = 1000;
+
+// 內聯引用改寫後的目標
+#[derive(Debug, Clone, Copy)]
+pub enum InlineTarget<'a> {
+    // 下載到目錄，引用改為本地路徑
+    Directory(&'a Path),
+    // 下載後以 data URI 內嵌
+    DataUri,
+}
+
+/// 將內容編碼為 data URI
+pub fn data_uri(content_type: &str, data: &[u8]) -> String {
+    let content_type = if content_type.is_empty() {
+        crate::mime::DEFAULT_MIME_TYPE
+    } else {
+        content_type
+    };
+    format!("data:{};base64,{}", content_type, BASE64.encode(data))
+}
+
+/// 將 markdown 中的內聯引用改寫為指定地址
+///
+/// bot 以 `![描述][inline_ref]` 或 `[描述][inline_ref]` 引用 File 事件中的檔案，
+/// 改寫後成為一般的 `![描述](地址)` 連結；`[inline_ref]: 地址` 形式的引用定義也會一併更新。
+/// targets 為 inline_ref 到地址的對應，未列出的引用保持不變。
+pub fn rewrite_inline_refs(markdown: &str, targets: &HashMap<String, String>) -> String {
+    let mut text = markdown.to_string();
+    for (inline_ref, target) in targets {
+        if inline_ref.is_empty() {
+            continue;
+        }
+        let destination = link_destination(target);
+        text = text.replace(
+            &format!("][{}]", inline_ref),
+            &format!("]({})", destination),
+        );
+        text = text
+            .split_inclusive('\n')
+            .map(|line| {
+                let definition = format!("[{}]:", inline_ref);
+                match line.trim_start().strip_prefix(&definition) {
+                    Some(_) => {
+                        let ending = &line[line.trim_end_matches(['\r', '\n']).len()..];
+                        format!("{} {}{}", definition, destination, ending)
+                    }
+                    None => line.to_string(),
+                }
+            })
+            .collect();
+    }
+    text
+}
+
+// 含空白或括號的地址需以尖括號包住才是有效的 markdown 連結
+fn link_destination(target: &str) -> String {
+    if target.contains(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '<' | '>')) {
+        format!("<{}>", target.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        target.to_string()
+    }
+}
+
+/// 去除檔名中的路徑及不安全字符，避免寫出目標目錄
+pub fn safe_file_name(name: &str) -> String {
+    let name = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, ':' | '*' | '?' | '"' | '<' | '>' | '|') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+    let name = name.trim().trim_start_matches('.');
+    if name.is_empty() {
+        "file".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// 依次嘗試 name、name (1)、name (2)…，以 create_new 開啟避免覆蓋已有檔案
+pub(crate) async fn create_unique_file(
+    dir: &Path,
+    name: &str,
+) -> std::io::Result<(PathBuf, tokio::fs::File)> {
+    let path = Path::new(name);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| name.to_string());
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    for attempt in 0..MAX_NAME_ATTEMPTS {
+        let candidate = match attempt {
+            0 => dir.join(name),
+            n => dir.join(format!("{} ({}){}", stem, n, extension)),
+        };
+        match tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+            .await
+        {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        format!("無法在 {} 建立不重複的檔名: {}", dir.display(), name),
+    ))
+}
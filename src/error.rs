@@ -29,6 +29,9 @@ pub enum PoeError {
     #[error("缺少必要的工具調用 ID: {0}")]
     MissingToolCallId(String),
 
+    #[error("無效的請求: {0}")]
+    InvalidRequest(String),
+
     // 新增文件上傳相關錯誤
     #[error("文件不存在: {0}")]
     FileNotFound(String),
@@ -45,6 +48,18 @@ pub enum PoeError {
     #[error("文件過大: {0}")]
     FileTooLarge(String),
 
+    #[error("上傳已取消")]
+    UploadCancelled,
+
+    #[error("文件下載失敗: {0}")]
+    FileDownloadFailed(String),
+
     #[error("無效的URL: {0}")]
     InvalidUrl(#[from] url::ParseError),
+
+    #[error("伺服器錯誤: {0}")]
+    ServerError(String),
+
+    #[error("配置錯誤: {0}")]
+    ConfigError(String),
 }
@@ -1,13 +1,36 @@
 pub mod client;
+pub mod conversation;
+pub mod download;
 pub mod error;
+pub mod mime;
+pub mod openai;
+pub mod sse;
+pub mod token;
 pub mod types;
+pub mod upload;
+pub mod upload_cache;
 
 #[cfg(feature = "xml")]
 pub mod xml;
 
+#[cfg(feature = "server")]
+pub mod server;
+
+#[cfg(feature = "proxy")]
+pub mod proxy;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "testing")]
+pub mod cassette;
+
+#[cfg(feature = "cli")]
+pub mod cli;
+
 #[cfg(test)]
 pub mod test;
 
-pub use client::{PoeClient, get_model_list};
+pub use client::{PoeClient, get_model_list, get_model_list_from};
 pub use error::PoeError;
 pub use types::*;
@@ -0,0 +1,212 @@
+use crate::error::PoeError;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+#[cfg(feature = "trace")]
+use tracing::debug;
+
+// 無法判斷類型時使用的預設 MIME 類型
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+// 檢測內容類型時讀取的檔案開頭長度
+pub const SNIFF_LEN: usize = 8192;
+
+/// 根據副檔名判斷 MIME 類型（不分大小寫）
+pub fn mime_from_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let mime_type = match extension.as_str() {
+        // 圖片
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "tif" | "tiff" => "image/tiff",
+        "ico" => "image/x-icon",
+        "heic" => "image/heic",
+        // 文件
+        "pdf" => "application/pdf",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "rtf" => "application/rtf",
+        "epub" => "application/epub+zip",
+        // 文本
+        "txt" | "log" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "tsv" => "text/tab-separated-values",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "yaml" | "yml" => "application/yaml",
+        "js" | "mjs" => "text/javascript",
+        "py" => "text/x-python",
+        "rs" => "text/x-rust",
+        // 音訊及影片
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" | "oga" => "audio/ogg",
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        // 壓縮檔
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        _ => return None,
+    };
+    Some(mime_type)
+}
+
+/// 根據檔案開頭的特徵位元組判斷 MIME 類型
+///
+/// 對於沒有特徵位元組的 UTF-8 文本返回 text/plain。
+pub fn mime_from_bytes(bytes: &[u8]) -> Option<&'static str> {
+    let starts_with = |signature: &[u8]| bytes.starts_with(signature);
+    let at = |offset: usize, signature: &[u8]| {
+        bytes
+            .get(offset..offset + signature.len())
+            .is_some_and(|slice| slice == signature)
+    };
+
+    let mime_type = if starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if starts_with(b"GIF87a") || starts_with(b"GIF89a") {
+        "image/gif"
+    } else if starts_with(b"RIFF") && at(8, b"WEBP") {
+        "image/webp"
+    } else if starts_with(b"RIFF") && at(8, b"WAVE") {
+        "audio/wav"
+    } else if starts_with(b"RIFF") && at(8, b"AVI ") {
+        "video/x-msvideo"
+    } else if starts_with(b"II*\0") || starts_with(b"MM\0*") {
+        "image/tiff"
+    } else if starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if starts_with(b"PK\x03\x04") {
+        zip_mime_type(bytes)
+    } else if starts_with(b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1") {
+        // 舊版 Office 複合文件格式，無法進一步區分
+        "application/x-ole-storage"
+    } else if starts_with(b"ID3")
+        || (bytes.len() >= 2 && bytes[0] == 0xff && bytes[1] & 0xe0 == 0xe0)
+    {
+        "audio/mpeg"
+    } else if at(4, b"ftyp") {
+        match bytes.get(8..12) {
+            Some(b"M4A ") => "audio/mp4",
+            Some(b"qt  ") => "video/quicktime",
+            Some(b"heic") | Some(b"heix") => "image/heic",
+            _ => "video/mp4",
+        }
+    } else if starts_with(b"OggS") {
+        "audio/ogg"
+    } else if starts_with(b"fLaC") {
+        "audio/flac"
+    } else if starts_with(b"\x1a\x45\xdf\xa3") {
+        "video/webm"
+    } else if starts_with(b"\x1f\x8b") {
+        "application/gzip"
+    } else if is_text(bytes) {
+        "text/plain"
+    } else {
+        return None;
+    };
+    Some(mime_type)
+}
+
+// 根據 ZIP 內的檔案名稱區分 Office Open XML 及 EPUB 格式
+fn zip_mime_type(bytes: &[u8]) -> &'static str {
+    let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|window| window == needle);
+    if contains(b"word/") {
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+    } else if contains(b"xl/") {
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+    } else if contains(b"ppt/") {
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+    } else if contains(b"application/epub+zip") {
+        "application/epub+zip"
+    } else {
+        "application/zip"
+    }
+}
+
+// 不含 NUL 及其他控制字符的 UTF-8 內容視為文本，允許截斷在多位元組字符中間
+fn is_text(bytes: &[u8]) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() && bytes.len() - e.valid_up_to() < 4 => {
+            // 只有結尾是不完整的字符
+            std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+    !text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0c'))
+}
+
+/// 綜合副檔名及內容判斷 MIME 類型
+///
+/// 二進制格式以特徵位元組為準；文本內容優先使用副檔名（例如 .csv、.json），
+/// 都無法判斷時返回 application/octet-stream。
+pub fn detect_mime_type(path: &Path, head: &[u8]) -> &'static str {
+    let from_extension = mime_from_extension(path);
+    let from_bytes = mime_from_bytes(head);
+    match from_bytes {
+        Some("text/plain") | None => from_extension.or(from_bytes).unwrap_or(DEFAULT_MIME_TYPE),
+        // 容器格式可能對應副檔名更具體的類型（例如 .epub、.doc）
+        Some(container @ ("application/zip" | "application/x-ole-storage")) => {
+            from_extension.unwrap_or(container)
+        }
+        Some(mime_type) => mime_type,
+    }
+}
+
+/// 讀取檔案開頭並判斷 MIME 類型
+pub async fn detect_file_mime_type(path: &Path) -> Result<&'static str, PoeError> {
+    let file = tokio::fs::File::open(path).await?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    file.take(SNIFF_LEN as u64).read_to_end(&mut head).await?;
+
+    let mime_type = detect_mime_type(path, &head);
+    #[cfg(feature = "trace")]
+    debug!("檢測到檔案 MIME 類型: {} -> {}", path.display(), mime_type);
+    Ok(mime_type)
+}
+
+/// 判斷 MIME 類型是否符合模式，支援 image/* 形式的通配及忽略參數（例如 ; charset=utf-8）
+pub fn mime_matches(pattern: &str, mime_type: &str) -> bool {
+    let essence = |value: &str| {
+        value
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    };
+    let (pattern, mime_type) = (essence(pattern), essence(mime_type));
+    if pattern == "*" || pattern == "*/*" {
+        return true;
+    }
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => mime_type
+            .split_once('/')
+            .is_some_and(|(mime_top_level, _)| mime_top_level == top_level),
+        None => pattern == mime_type,
+    }
+}
@@ -0,0 +1,851 @@
+use crate::error::PoeError;
+use crate::types::{
+    Attachment, ChatEventType, ChatMessage, ChatRequest, ChatResponse, ChatResponseData, ChatTool,
+    ChatToolCall, ChatToolResult, FunctionCall, PartialToolCall, Role,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+#[cfg(feature = "trace")]
+use tracing::{debug, warn};
+
+// 單個回覆中工具調用的數量上限，避免遠端提供的 index 導致無上限的記憶體分配
+pub const MAX_TOOL_CALLS: usize = 128;
+
+// OpenAI 相容的消息角色
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OpenAiRole {
+    System,
+    Developer,
+    #[default]
+    User,
+    Assistant,
+    Tool,
+}
+
+// 消息內容，可為純文本或多段內容
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// 取得所有文本內容（多段內容以換行連接）
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+// 多段內容中的單段
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+// 圖片 URL 內容
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+// OpenAI 相容的對話消息
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OpenAiMessage {
+    pub role: OpenAiRole,
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ChatToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl OpenAiMessage {
+    pub fn new(role: OpenAiRole, content: impl Into<MessageContent>) -> Self {
+        Self {
+            role,
+            content: Some(content.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn system(content: impl Into<MessageContent>) -> Self {
+        Self::new(OpenAiRole::System, content)
+    }
+
+    pub fn user(content: impl Into<MessageContent>) -> Self {
+        Self::new(OpenAiRole::User, content)
+    }
+
+    pub fn assistant(content: impl Into<MessageContent>) -> Self {
+        Self::new(OpenAiRole::Assistant, content)
+    }
+
+    /// 建立工具結果消息
+    pub fn tool(tool_call_id: &str, content: impl Into<MessageContent>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.to_string()),
+            ..Self::new(OpenAiRole::Tool, content)
+        }
+    }
+
+    /// 消息的文本內容
+    pub fn text(&self) -> String {
+        self.content
+            .as_ref()
+            .map(MessageContent::text)
+            .unwrap_or_default()
+    }
+}
+
+// 串流選項
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+// /v1/chat/completions 請求
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<OpenAiMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ChatTool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    // 尚未建模的請求欄位
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChatCompletionRequest {
+    pub fn new(model: &str, messages: Vec<OpenAiMessage>) -> Self {
+        Self {
+            model: model.to_string(),
+            messages,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_tools(mut self, tools: Vec<ChatTool>) -> Self {
+        self.tools = Some(tools);
+        self
+    }
+}
+
+// token 用量
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct CompletionUsage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub completion_tokens: u32,
+    #[serde(default)]
+    pub total_tokens: u32,
+}
+
+// 非串流回應中的單個選項
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatCompletionChoice {
+    pub index: u32,
+    pub message: OpenAiMessage,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+// /v1/chat/completions 非串流回應
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatCompletion {
+    pub id: String,
+    #[serde(default)]
+    pub object: String,
+    #[serde(default)]
+    pub created: i64,
+    #[serde(default)]
+    pub model: String,
+    pub choices: Vec<ChatCompletionChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<CompletionUsage>,
+}
+
+impl ChatCompletion {
+    /// 第一個選項的文本內容
+    pub fn text(&self) -> String {
+        self.choices
+            .first()
+            .map(|choice| choice.message.text())
+            .unwrap_or_default()
+    }
+
+    /// 第一個選項的工具調用
+    pub fn tool_calls(&self) -> &[ChatToolCall] {
+        self.choices
+            .first()
+            .and_then(|choice| choice.message.tool_calls.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+// 串流中的工具調用增量
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCallDelta>,
+}
+
+// 工具調用函數的增量
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct FunctionCallDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+// 串流中的消息增量
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ChunkDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<OpenAiRole>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+// 串流回應中的單個選項
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChunkChoice {
+    pub index: u32,
+    #[serde(default)]
+    pub delta: ChunkDelta,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+// chat.completion.chunk 串流回應
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    #[serde(default)]
+    pub object: String,
+    #[serde(default)]
+    pub created: i64,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub choices: Vec<ChunkChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<CompletionUsage>,
+}
+
+impl ChatCompletionChunk {
+    /// 第一個選項的文本增量
+    pub fn delta_text(&self) -> Option<&str> {
+        self.choices
+            .first()
+            .and_then(|choice| choice.delta.content.as_deref())
+    }
+
+    /// 第一個選項的結束原因
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.choices
+            .first()
+            .and_then(|choice| choice.finish_reason.as_deref())
+    }
+}
+
+// 將串流的工具調用增量累積為完整的 ChatToolCall
+#[derive(Debug, Clone, Default)]
+pub struct ToolCallAccumulator {
+    partial_calls: Vec<PartialToolCall>,
+}
+
+impl ToolCallAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 累積一個串流塊中的工具調用增量
+    pub fn push_chunk(&mut self, chunk: &ChatCompletionChunk) {
+        for choice in &chunk.choices {
+            if let Some(ref deltas) = choice.delta.tool_calls {
+                for delta in deltas {
+                    self.push_delta(delta);
+                }
+            }
+        }
+    }
+
+    /// 累積單個工具調用增量，index 超過 MAX_TOOL_CALLS 的增量會被忽略
+    pub fn push_delta(&mut self, delta: &ToolCallDelta) {
+        if delta.index >= MAX_TOOL_CALLS {
+            #[cfg(feature = "trace")]
+            warn!("忽略超出上限的工具調用 index: {}", delta.index);
+            return;
+        }
+        while self.partial_calls.len() <= delta.index {
+            self.partial_calls.push(PartialToolCall::default());
+        }
+        let partial = &mut self.partial_calls[delta.index];
+        if let Some(ref id) = delta.id {
+            partial.id = id.clone();
+        }
+        if let Some(ref r#type) = delta.r#type {
+            partial.r#type = r#type.clone();
+        }
+        if let Some(ref function) = delta.function {
+            if let Some(ref name) = function.name {
+                partial.function_name.push_str(name);
+            }
+            if let Some(ref arguments) = function.arguments {
+                partial.function_arguments.push_str(arguments);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partial_calls.is_empty()
+    }
+
+    /// 取得已累積的完整工具調用
+    pub fn finish(self) -> Vec<ChatToolCall> {
+        self.partial_calls
+            .into_iter()
+            .map(|partial| ChatToolCall {
+                id: partial.id,
+                r#type: if partial.r#type.is_empty() {
+                    "function".to_string()
+                } else {
+                    partial.r#type
+                },
+                function: FunctionCall {
+                    name: partial.function_name,
+                    arguments: partial.function_arguments,
+                },
+            })
+            .collect()
+    }
+}
+
+impl From<&Role> for OpenAiRole {
+    fn from(role: &Role) -> Self {
+        match role {
+            Role::System => OpenAiRole::System,
+            Role::Bot => OpenAiRole::Assistant,
+            Role::Tool => OpenAiRole::Tool,
+            // Poe 的 user 及未知角色皆視為用戶消息
+            Role::User | Role::Other(_) => OpenAiRole::User,
+        }
+    }
+}
+
+impl From<OpenAiRole> for Role {
+    fn from(role: OpenAiRole) -> Self {
+        match role {
+            OpenAiRole::System | OpenAiRole::Developer => Role::System,
+            OpenAiRole::User => Role::User,
+            OpenAiRole::Assistant => Role::Bot,
+            OpenAiRole::Tool => Role::Tool,
+        }
+    }
+}
+
+// 判斷附件是否為圖片
+fn is_image_attachment(attachment: &Attachment) -> bool {
+    attachment
+        .content_type
+        .as_deref()
+        .is_some_and(|content_type| content_type.starts_with("image/"))
+        || attachment.url.starts_with("data:image/")
+}
+
+// 從 data URI 中取得 MIME 類型
+fn data_uri_content_type(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("data:")?;
+    let end = rest.find([';', ','])?;
+    Some(rest[..end].to_string()).filter(|content_type| !content_type.is_empty())
+}
+
+// 從消息的 extra 中讀取欄位
+fn extra_field<T: serde::de::DeserializeOwned>(message: &ChatMessage, key: &str) -> Option<T> {
+    message
+        .extra
+        .get(key)
+        .and_then(|value| serde_json::from_value(value.clone()).ok())
+}
+
+impl From<&ChatMessage> for OpenAiMessage {
+    fn from(message: &ChatMessage) -> Self {
+        let attachments = message.attachments.as_deref().unwrap_or_default();
+        let content = if attachments.is_empty() {
+            MessageContent::Text(message.content.clone())
+        } else {
+            // 圖片附件轉為 image_url，其他附件以連結形式附加到文本中
+            let mut text = message.content.clone();
+            let mut image_parts = Vec::new();
+            for attachment in attachments {
+                if is_image_attachment(attachment) {
+                    image_parts.push(ContentPart::ImageUrl {
+                        image_url: ImageUrl {
+                            url: attachment.url.clone(),
+                            detail: None,
+                        },
+                    });
+                } else {
+                    let name = attachment.name.as_deref().unwrap_or("附件");
+                    text.push_str(&format!("\n[{}]({})", name, attachment.url));
+                }
+            }
+            let mut parts = vec![ContentPart::Text { text }];
+            parts.extend(image_parts);
+            MessageContent::Parts(parts)
+        };
+
+        // 還原轉換時保存在 extra 中的工具欄位
+        Self {
+            role: OpenAiRole::from(&message.role),
+            content: Some(content),
+            name: extra_field(message, "name"),
+            tool_calls: extra_field(message, "tool_calls"),
+            tool_call_id: extra_field(message, "tool_call_id"),
+        }
+    }
+}
+
+impl From<&OpenAiMessage> for ChatMessage {
+    fn from(message: &OpenAiMessage) -> Self {
+        let mut chat_message = ChatMessage::new(Role::from(message.role), message.text());
+        if let Some(MessageContent::Parts(ref parts)) = message.content {
+            for part in parts {
+                if let ContentPart::ImageUrl { image_url } = part {
+                    chat_message = chat_message.with_attachment(Attachment {
+                        url: image_url.url.clone(),
+                        content_type: data_uri_content_type(&image_url.url),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+        // Poe 消息沒有對應的工具欄位，保存在 extra 中以免遺失
+        if let Some(ref tool_calls) = message.tool_calls
+            && let Ok(value) = serde_json::to_value(tool_calls)
+        {
+            chat_message.extra.insert("tool_calls".to_string(), value);
+        }
+        if let Some(ref tool_call_id) = message.tool_call_id {
+            chat_message.extra.insert(
+                "tool_call_id".to_string(),
+                Value::from(tool_call_id.clone()),
+            );
+        }
+        if message.role == OpenAiRole::Tool
+            && let Some(ref name) = message.name
+        {
+            chat_message
+                .extra
+                .insert("name".to_string(), Value::from(name.clone()));
+        }
+        chat_message
+    }
+}
+
+impl From<&ChatToolResult> for OpenAiMessage {
+    fn from(result: &ChatToolResult) -> Self {
+        Self {
+            name: Some(result.name.clone()),
+            ..OpenAiMessage::tool(&result.tool_call_id, result.content.as_str())
+        }
+    }
+}
+
+impl TryFrom<&OpenAiMessage> for ChatToolResult {
+    type Error = PoeError;
+
+    fn try_from(message: &OpenAiMessage) -> Result<Self, Self::Error> {
+        if message.role != OpenAiRole::Tool {
+            return Err(PoeError::ToolResultParseFailed(format!(
+                "消息角色不是 tool: {:?}",
+                message.role
+            )));
+        }
+        let tool_call_id = message
+            .tool_call_id
+            .clone()
+            .ok_or_else(|| PoeError::MissingToolCallId("tool 消息缺少 tool_call_id".to_string()))?;
+        Ok(ChatToolResult {
+            role: "tool".to_string(),
+            tool_call_id,
+            name: message.name.clone().unwrap_or_default(),
+            content: message.text(),
+        })
+    }
+}
+
+impl TryFrom<&[OpenAiMessage]> for ChatRequest {
+    type Error = PoeError;
+
+    /// 將 OpenAI 消息陣列轉換為 Poe 請求
+    ///
+    /// 末尾的工具調用回合（帶 tool_calls 的 assistant 消息及其後的 tool 消息）
+    /// 會轉為請求的 tool_calls 及 tool_results；較早的工具消息則保留為對應角色的消息，
+    /// tool_calls 與 tool_call_id 保存在消息的 extra 中。
+    fn try_from(messages: &[OpenAiMessage]) -> Result<Self, Self::Error> {
+        if messages.is_empty() {
+            return Err(PoeError::InvalidRequest("消息陣列不能為空".to_string()));
+        }
+
+        // 找出末尾工具回合的起點
+        let trailing_tools = messages
+            .iter()
+            .rev()
+            .take_while(|message| message.role == OpenAiRole::Tool)
+            .count();
+        let round_start = messages.len() - trailing_tools;
+        let trailing_round = trailing_tools > 0
+            && round_start > 0
+            && messages[round_start - 1].role == OpenAiRole::Assistant
+            && messages[round_start - 1].tool_calls.is_some();
+        let history_end = if trailing_round {
+            round_start - 1
+        } else {
+            messages.len()
+        };
+
+        let mut query = Vec::with_capacity(history_end + 1);
+        for message in &messages[..history_end] {
+            query.push(ChatMessage::from(message));
+        }
+
+        let mut request = ChatRequest {
+            query,
+            ..Default::default()
+        };
+
+        if trailing_round {
+            let assistant = &messages[round_start - 1];
+            let tool_calls = assistant.tool_calls.clone().unwrap_or_default();
+            if !assistant.text().is_empty() {
+                request.query.push(ChatMessage::from(assistant));
+            }
+            let tool_results = messages[round_start..]
+                .iter()
+                .map(|message| {
+                    let mut result = ChatToolResult::try_from(message)?;
+                    if result.name.is_empty() {
+                        // 從對應的工具調用中補上工具名稱
+                        result.name = tool_calls
+                            .iter()
+                            .find(|call| call.id == result.tool_call_id)
+                            .map(|call| call.function.name.clone())
+                            .ok_or_else(|| {
+                                PoeError::MissingToolCallId(result.tool_call_id.clone())
+                            })?;
+                    }
+                    Ok(result)
+                })
+                .collect::<Result<Vec<_>, PoeError>>()?;
+
+            #[cfg(feature = "trace")]
+            debug!(
+                "轉換末尾工具回合: {} 個調用, {} 個結果",
+                tool_calls.len(),
+                tool_results.len()
+            );
+
+            request.tool_calls = Some(tool_calls);
+            request.tool_results = Some(tool_results);
+        }
+
+        Ok(request)
+    }
+}
+
+// 由 Poe 協議定義、不能由 OpenAI 請求覆蓋的欄位
+const RESERVED_POE_FIELDS: [&str; 10] = [
+    "version",
+    "type",
+    "query",
+    "user_id",
+    "conversation_id",
+    "message_id",
+    "tool_calls",
+    "tool_results",
+    "metadata",
+    "skip_system_prompt",
+];
+
+impl TryFrom<&ChatCompletionRequest> for ChatRequest {
+    type Error = PoeError;
+
+    /// 將 OpenAI 請求轉換為 Poe 請求
+    ///
+    /// temperature、stop、tools、user 及 logit_bias 對應到 Poe 協議欄位；
+    /// top_p、max_tokens、tool_choice 及其他未建模欄位放入 extra 原樣轉發，由 bot 決定是否採用。
+    /// stream 及 stream_options 只影響回應格式，不會轉發。
+    /// 與 Poe 協議欄位同名（如 query、tool_calls、metadata）的欄位不受支援，返回 InvalidRequest。
+    fn try_from(completion: &ChatCompletionRequest) -> Result<Self, Self::Error> {
+        let mut request = ChatRequest::try_from(completion.messages.as_slice())?;
+        request.tools = completion.tools.clone();
+        request.temperature = completion.temperature;
+        request.stop_sequences = completion.stop.clone();
+        if let Some(ref user) = completion.user {
+            request.user_id = user.clone();
+        }
+
+        if let Some(top_p) = completion.top_p {
+            request
+                .extra
+                .insert("top_p".to_string(), Value::from(top_p));
+        }
+        if let Some(max_tokens) = completion.max_tokens {
+            request
+                .extra
+                .insert("max_tokens".to_string(), Value::from(max_tokens));
+        }
+        if let Some(ref tool_choice) = completion.tool_choice {
+            request
+                .extra
+                .insert("tool_choice".to_string(), tool_choice.clone());
+        }
+        for (key, value) in &completion.extra {
+            if key == "logit_bias" {
+                request.logit_bias =
+                    Some(serde_json::from_value(value.clone()).map_err(|e| {
+                        PoeError::InvalidRequest(format!("無效的 logit_bias: {}", e))
+                    })?);
+            } else if RESERVED_POE_FIELDS.contains(&key.as_str()) {
+                return Err(PoeError::InvalidRequest(format!(
+                    "不支援的請求欄位: {}",
+                    key
+                )));
+            } else {
+                request.extra.insert(key.clone(), value.clone());
+            }
+        }
+
+        #[cfg(feature = "trace")]
+        debug!("轉發的額外請求欄位: {:?}", request.extra.keys());
+
+        Ok(request)
+    }
+}
+
+impl From<&ChatRequest> for Vec<OpenAiMessage> {
+    /// 將 Poe 請求轉換為 OpenAI 消息陣列，工具調用及結果附加在末尾
+    fn from(request: &ChatRequest) -> Self {
+        let mut messages: Vec<OpenAiMessage> =
+            request.query.iter().map(OpenAiMessage::from).collect();
+        if let Some(ref tool_calls) = request.tool_calls {
+            messages.push(OpenAiMessage {
+                role: OpenAiRole::Assistant,
+                content: None,
+                tool_calls: Some(tool_calls.clone()),
+                ..Default::default()
+            });
+        }
+        if let Some(ref tool_results) = request.tool_results {
+            messages.extend(tool_results.iter().map(OpenAiMessage::from));
+        }
+        messages
+    }
+}
+
+// 將 Poe 串流事件轉換為 chat.completion.chunk
+#[derive(Debug, Clone)]
+pub struct ChunkConverter {
+    id: String,
+    model: String,
+    created: i64,
+    role_sent: bool,
+    tool_call_count: usize,
+    sent_text: String,
+}
+
+impl ChunkConverter {
+    pub fn new(id: &str, model: &str, created: i64) -> Self {
+        Self {
+            id: id.to_string(),
+            model: model.to_string(),
+            created,
+            role_sent: false,
+            tool_call_count: 0,
+            sent_text: String::new(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn created(&self) -> i64 {
+        self.created
+    }
+
+    /// 建立只帶用量、不含選項的串流塊（對應 stream_options.include_usage）
+    pub fn usage_chunk(&self, usage: CompletionUsage) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            choices: Vec::new(),
+            usage: Some(usage),
+            ..self.chunk(ChunkDelta::default(), None)
+        }
+    }
+
+    fn chunk(&self, delta: ChunkDelta, finish_reason: Option<&str>) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta,
+                finish_reason: finish_reason.map(str::to_string),
+            }],
+            usage: None,
+        }
+    }
+
+    // 第一個增量帶上 assistant 角色
+    fn next_role(&mut self) -> Option<OpenAiRole> {
+        if self.role_sent {
+            None
+        } else {
+            self.role_sent = true;
+            Some(OpenAiRole::Assistant)
+        }
+    }
+
+    // 建立文字增量並記錄已送出的內容
+    fn text_chunk(&mut self, text: &str) -> ChatCompletionChunk {
+        self.sent_text.push_str(text);
+        let delta = ChunkDelta {
+            role: self.next_role(),
+            content: Some(text.to_string()),
+            tool_calls: None,
+        };
+        self.chunk(delta, None)
+    }
+
+    /// 轉換單個 Poe 事件，無對應增量的事件返回 None
+    ///
+    /// 已送出的增量無法撤回：replace_response 延續已送出的文字時只送出新增部分，
+    /// 否則結束已送出的文字並另起段落送出替換內容。錯誤事件不屬於串流塊，由調用方處理；
+    /// done 事件產生帶結束原因的最後一個串流塊。
+    pub fn convert(&mut self, response: &ChatResponse) -> Option<ChatCompletionChunk> {
+        match (&response.event, &response.data) {
+            (ChatEventType::Text, Some(ChatResponseData::Text { text })) => {
+                Some(self.text_chunk(text))
+            }
+            (ChatEventType::ReplaceResponse, Some(ChatResponseData::Text { text })) => {
+                let text = match text.strip_prefix(self.sent_text.as_str()) {
+                    Some(rest) => rest.to_string(),
+                    None if self.sent_text.is_empty() => text.clone(),
+                    None => {
+                        #[cfg(feature = "trace")]
+                        warn!("replace_response 與已送出的內容不一致，另起段落送出替換內容");
+                        format!("\n\n{}", text)
+                    }
+                };
+                (!text.is_empty()).then(|| self.text_chunk(&text))
+            }
+            (_, Some(ChatResponseData::ToolCalls(tool_calls))) => {
+                let deltas = tool_calls
+                    .iter()
+                    .map(|call| {
+                        let index = self.tool_call_count;
+                        self.tool_call_count += 1;
+                        ToolCallDelta {
+                            index,
+                            id: Some(call.id.clone()),
+                            r#type: Some(call.r#type.clone()),
+                            function: Some(FunctionCallDelta {
+                                name: Some(call.function.name.clone()),
+                                arguments: Some(call.function.arguments.clone()),
+                            }),
+                        }
+                    })
+                    .collect();
+                let delta = ChunkDelta {
+                    role: self.next_role(),
+                    content: None,
+                    tool_calls: Some(deltas),
+                };
+                Some(self.chunk(delta, None))
+            }
+            (ChatEventType::Done, _) => {
+                let finish_reason = if self.tool_call_count > 0 {
+                    "tool_calls"
+                } else {
+                    "stop"
+                };
+                Some(self.chunk(ChunkDelta::default(), Some(finish_reason)))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use crate::client::PoeClient;
+pub use crate::client::{DEFAULT_POE_BASE_URL, DEFAULT_POE_FILE_UPLOAD_URL};
+use crate::error::PoeError;
+use crate::openai::{
+    ChatCompletion, ChatCompletionChoice, ChatCompletionRequest, ChunkConverter, CompletionUsage,
+    OpenAiMessage, OpenAiRole,
+};
+use crate::server::{bearer_token, constant_time_eq};
+use crate::token::{TokenEstimator, UsageTracker};
+use crate::types::*;
+use axum::Router;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{get, post};
+use futures_util::{Stream, StreamExt};
+use serde_json::json;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::ToSocketAddrs;
+#[cfg(feature = "trace")]
+use tracing::{debug, warn};
+
+// 將 OpenAI 相容請求轉發到 Poe 的代理伺服器
+//
+// 未設置 access key 時，調用方以自己的 Poe access key 作為 Bearer token；
+// 設置 access key 後，調用方需以 proxy key 作為 Bearer token，由代理使用伺服器的 access key。
+#[derive(Clone)]
+pub struct OpenAiProxy {
+    access_key: Option<String>,
+    proxy_key: Option<String>,
+    poe_base_url: String,
+    poe_file_upload_url: String,
+    estimator: TokenEstimator,
+}
+
+type ChatStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, PoeError>> + Send>>;
+
+impl OpenAiProxy {
+    /// 建立代理，未設置 access key 時使用請求 Authorization 標頭中的 Bearer token
+    pub fn new(access_key: Option<&str>) -> Self {
+        Self {
+            access_key: access_key.map(str::to_string),
+            proxy_key: None,
+            poe_base_url: DEFAULT_POE_BASE_URL.to_string(),
+            poe_file_upload_url: DEFAULT_POE_FILE_UPLOAD_URL.to_string(),
+            estimator: TokenEstimator::default(),
+        }
+    }
+
+    /// 設置調用方必須攜帶的 proxy key，用於保護伺服器的 access key
+    pub fn with_proxy_key(mut self, proxy_key: &str) -> Self {
+        self.proxy_key = Some(proxy_key.to_string());
+        self
+    }
+
+    /// 設置上游 Poe API 地址
+    pub fn with_base_url(mut self, poe_base_url: &str) -> Self {
+        self.poe_base_url = poe_base_url.to_string();
+        self
+    }
+
+    /// 設置上游檔案上傳地址
+    pub fn with_file_upload_url(mut self, poe_file_upload_url: &str) -> Self {
+        self.poe_file_upload_url = poe_file_upload_url.to_string();
+        self
+    }
+
+    /// 設置用於估算用量的 token 估算器
+    pub fn with_estimator(mut self, estimator: TokenEstimator) -> Self {
+        self.estimator = estimator;
+        self
+    }
+
+    /// 建立處理 /v1/chat/completions 及 /v1/models 的路由
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/v1/chat/completions", post(handle_chat_completions))
+            .route("/v1/models", get(handle_models))
+            .with_state(Arc::new(self))
+    }
+
+    /// 在指定地址啟動代理伺服器
+    ///
+    /// 設置了 access key 但未設置 proxy key 時，只允許監聽本機回環地址，
+    /// 以免任何能連線的人都能使用伺服器的 access key。
+    pub async fn serve(self, addr: impl ToSocketAddrs) -> Result<(), PoeError> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| PoeError::ServerError(format!("無法綁定地址: {}", e)))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| PoeError::ServerError(format!("無法取得監聽地址: {}", e)))?;
+        if self.access_key.is_some() && self.proxy_key.is_none() && !local_addr.ip().is_loopback() {
+            return Err(PoeError::ServerError(format!(
+                "未設置 proxy key 時不能在非本機地址 {} 上使用伺服器的 access key",
+                local_addr
+            )));
+        }
+
+        #[cfg(feature = "trace")]
+        debug!("OpenAI 相容代理開始監聽: {:?}", listener.local_addr());
+
+        axum::serve(listener, self.router())
+            .await
+            .map_err(|e| PoeError::ServerError(e.to_string()))
+    }
+
+    // 取得本次請求使用的 access key，使用伺服器的 access key 時需驗證 proxy key
+    fn resolve_access_key(&self, headers: &HeaderMap) -> Option<String> {
+        let token = bearer_token(headers);
+        match (&self.access_key, &self.proxy_key) {
+            (Some(access_key), Some(proxy_key)) => token
+                .is_some_and(|token| constant_time_eq(token, proxy_key))
+                .then(|| access_key.clone()),
+            (Some(access_key), None) => Some(access_key.clone()),
+            (None, _) => token.map(str::to_string),
+        }
+    }
+
+    fn client(&self, bot_name: &str, access_key: &str) -> PoeClient {
+        PoeClient::new(
+            bot_name,
+            access_key,
+            &self.poe_base_url,
+            &self.poe_file_upload_url,
+        )
+    }
+}
+
+// OpenAI 格式的錯誤回應
+fn error_response(status: StatusCode, error_type: &str, message: &str) -> Response {
+    (
+        status,
+        Json(json!({
+            "error": {
+                "message": message,
+                "type": error_type,
+            }
+        })),
+    )
+        .into_response()
+}
+
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn to_usage(usage: crate::token::UsageEstimate) -> CompletionUsage {
+    CompletionUsage {
+        prompt_tokens: usage.input_tokens as u32,
+        completion_tokens: usage.output_tokens as u32,
+        total_tokens: (usage.input_tokens + usage.output_tokens) as u32,
+    }
+}
+
+async fn handle_models(State(proxy): State<Arc<OpenAiProxy>>, headers: HeaderMap) -> Response {
+    let Some(access_key) = proxy.resolve_access_key(&headers) else {
+        return error_response(
+            StatusCode::UNAUTHORIZED,
+            "authentication_error",
+            "Missing or invalid access key",
+        );
+    };
+
+    match proxy.client("", &access_key).get_v1_model_list().await {
+        Ok(models) => Json(json!({ "object": "list", "data": models.data })).into_response(),
+        Err(e) => {
+            #[cfg(feature = "trace")]
+            warn!("代理獲取模型列表失敗: {}", e);
+            error_response(StatusCode::BAD_GATEWAY, "upstream_error", &e.to_string())
+        }
+    }
+}
+
+async fn handle_chat_completions(
+    State(proxy): State<Arc<OpenAiProxy>>,
+    headers: HeaderMap,
+    Json(completion): Json<ChatCompletionRequest>,
+) -> Response {
+    let Some(access_key) = proxy.resolve_access_key(&headers) else {
+        return error_response(
+            StatusCode::UNAUTHORIZED,
+            "authentication_error",
+            "Missing or invalid access key",
+        );
+    };
+
+    let request = match ChatRequest::try_from(&completion) {
+        Ok(request) => request,
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                &e.to_string(),
+            );
+        }
+    };
+
+    #[cfg(feature = "trace")]
+    debug!(
+        "代理 chat/completions 請求 | model: {} | stream: {:?}",
+        completion.model, completion.stream
+    );
+
+    let tracker = UsageTracker::new(proxy.estimator.clone(), &request);
+    let stream = match proxy
+        .client(&completion.model, &access_key)
+        .stream_request(request)
+        .await
+    {
+        Ok(stream) => stream,
+        Err(e) => {
+            #[cfg(feature = "trace")]
+            warn!("代理請求 Poe 失敗: {}", e);
+            return error_response(StatusCode::BAD_GATEWAY, "upstream_error", &e.to_string());
+        }
+    };
+
+    let id = format!("chatcmpl-{}", unix_timestamp());
+    let converter = ChunkConverter::new(&id, &completion.model, unix_timestamp());
+    if completion.stream.unwrap_or(false) {
+        let include_usage = completion
+            .stream_options
+            .as_ref()
+            .is_some_and(|options| options.include_usage);
+        stream_completion(stream, converter, tracker, include_usage)
+    } else {
+        collect_completion(stream, converter, tracker).await
+    }
+}
+
+// 以 OpenAI SSE 格式串流返回，結束時追加 [DONE]
+fn stream_completion(
+    stream: ChatStream,
+    mut converter: ChunkConverter,
+    mut tracker: UsageTracker,
+    include_usage: bool,
+) -> Response {
+    let mut stopped = false;
+    let events = stream
+        .scan((), move |_, response| {
+            if stopped {
+                return futures_util::future::ready(None);
+            }
+            let mut events = Vec::new();
+            match response {
+                Ok(ChatResponse {
+                    data: Some(ChatResponseData::Error { text, .. }),
+                    ..
+                }) => {
+                    events.push(upstream_error_data(&text));
+                    stopped = true;
+                }
+                Ok(response) => {
+                    tracker.observe(&response);
+                    if let Some(chunk) = converter.convert(&response) {
+                        events.push(serde_json::to_string(&chunk).unwrap_or_default());
+                    }
+                    if matches!(response.event, ChatEventType::Done) {
+                        if include_usage {
+                            let chunk = converter.usage_chunk(to_usage(tracker.usage()));
+                            events.push(serde_json::to_string(&chunk).unwrap_or_default());
+                        }
+                        stopped = true;
+                    }
+                }
+                Err(e) => {
+                    #[cfg(feature = "trace")]
+                    warn!("代理串流出錯: {}", e);
+                    events.push(upstream_error_data(&e.to_string()));
+                    stopped = true;
+                }
+            }
+            futures_util::future::ready(Some(events))
+        })
+        .flat_map(futures_util::stream::iter)
+        .chain(futures_util::stream::once(async { "[DONE]".to_string() }))
+        .map(|data| Ok::<Event, Infallible>(Event::default().data(data)));
+
+    Sse::new(events).into_response()
+}
+
+// 串流中途的錯誤對象
+fn upstream_error_data(message: &str) -> String {
+    json!({ "error": { "message": message, "type": "upstream_error" } }).to_string()
+}
+
+// 收集完整回應後一次性返回
+async fn collect_completion(
+    mut stream: ChatStream,
+    converter: ChunkConverter,
+    mut tracker: UsageTracker,
+) -> Response {
+    let mut tool_calls = Vec::new();
+    while let Some(response) = stream.next().await {
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                return error_response(StatusCode::BAD_GATEWAY, "upstream_error", &e.to_string());
+            }
+        };
+        match response.data {
+            Some(ChatResponseData::Error { ref text, .. }) => {
+                return error_response(StatusCode::BAD_GATEWAY, "upstream_error", text);
+            }
+            Some(ChatResponseData::ToolCalls(ref calls)) => tool_calls.extend(calls.clone()),
+            _ => {}
+        }
+        tracker.observe(&response);
+        if matches!(response.event, ChatEventType::Done) {
+            break;
+        }
+    }
+
+    let finish_reason = if tool_calls.is_empty() {
+        "stop"
+    } else {
+        "tool_calls"
+    };
+    let completion = ChatCompletion {
+        id: converter.id().to_string(),
+        object: "chat.completion".to_string(),
+        created: converter.created(),
+        model: converter.model().to_string(),
+        choices: vec![ChatCompletionChoice {
+            index: 0,
+            message: OpenAiMessage {
+                role: OpenAiRole::Assistant,
+                content: Some(tracker.output_text().into()),
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                ..Default::default()
+            },
+            finish_reason: Some(finish_reason.to_string()),
+        }],
+        usage: Some(to_usage(tracker.usage())),
+    };
+    Json(completion).into_response()
+}
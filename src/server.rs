@@ -0,0 +1,399 @@
+use crate::error::PoeError;
+use crate::types::*;
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode, header::AUTHORIZATION};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::post;
+use futures_util::{Stream, StreamExt};
+use serde_json::{Value, json};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::net::ToSocketAddrs;
+#[cfg(feature = "trace")]
+use tracing::{debug, warn};
+
+// 預設的 ping 間隔
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(15);
+
+// 伺服器端 bot 輸出的事件
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoeEvent {
+    Text(String),
+    ReplaceResponse(String),
+    Json(Value),
+    File(FileData),
+    Meta(MetaData),
+    SuggestedReply(String),
+    Error { text: String, allow_retry: bool },
+    // 未建模的事件，按原始名稱及數據轉發
+    Raw { name: String, data: String },
+    Done,
+}
+
+// 伺服器端事件串流
+pub type PoeEventStream = Pin<Box<dyn Stream<Item = PoeEvent> + Send>>;
+
+impl PoeEvent {
+    pub fn text(text: impl Into<String>) -> Self {
+        PoeEvent::Text(text.into())
+    }
+
+    pub fn replace_response(text: impl Into<String>) -> Self {
+        PoeEvent::ReplaceResponse(text.into())
+    }
+
+    pub fn suggested_reply(text: impl Into<String>) -> Self {
+        PoeEvent::SuggestedReply(text.into())
+    }
+
+    pub fn error(text: impl Into<String>, allow_retry: bool) -> Self {
+        PoeEvent::Error {
+            text: text.into(),
+            allow_retry,
+        }
+    }
+
+    /// SSE 事件名稱
+    pub fn event_name(&self) -> &str {
+        match self {
+            PoeEvent::Text(_) => "text",
+            PoeEvent::ReplaceResponse(_) => "replace_response",
+            PoeEvent::Json(_) => "json",
+            PoeEvent::File(_) => "file",
+            PoeEvent::Meta(_) => "meta",
+            PoeEvent::SuggestedReply(_) => "suggested_reply",
+            PoeEvent::Error { .. } => "error",
+            PoeEvent::Raw { name, .. } => name,
+            PoeEvent::Done => "done",
+        }
+    }
+
+    /// SSE 事件數據，Raw 事件原樣返回，其餘為單行 JSON
+    pub fn data(&self) -> String {
+        let value = match self {
+            PoeEvent::Raw { data, .. } => return data.clone(),
+            PoeEvent::Text(text)
+            | PoeEvent::ReplaceResponse(text)
+            | PoeEvent::SuggestedReply(text) => json!({ "text": text }),
+            PoeEvent::Json(value) => value.clone(),
+            PoeEvent::File(file) => serde_json::to_value(file).unwrap_or_default(),
+            PoeEvent::Meta(meta) => serde_json::to_value(meta).unwrap_or_default(),
+            PoeEvent::Error { text, allow_retry } => {
+                json!({ "text": text, "allow_retry": allow_retry })
+            }
+            PoeEvent::Done => json!({}),
+        };
+        value.to_string()
+    }
+
+    /// 編碼為 SSE 格式文本
+    pub fn encode(&self) -> String {
+        format!("event: {}\ndata: {}\n\n", self.event_name(), self.data())
+    }
+
+    fn to_sse_event(&self) -> Event {
+        Event::default().event(self.event_name()).data(self.data())
+    }
+}
+
+impl PoeEvent {
+    /// 將客戶端收到的事件轉換為伺服器端事件，done 事件返回 None
+    ///
+    /// 未知事件轉為 Raw 事件原樣轉發，不含工具調用的 json 事件轉為 Json 事件。
+    pub fn from_chat_response(response: ChatResponse) -> Option<Self> {
+        match (response.event, response.data) {
+            (ChatEventType::Text, Some(ChatResponseData::Text { text })) => {
+                Some(PoeEvent::Text(text))
+            }
+            (ChatEventType::ReplaceResponse, Some(ChatResponseData::Text { text })) => {
+                Some(PoeEvent::ReplaceResponse(text))
+            }
+            (ChatEventType::SuggestedReply, Some(ChatResponseData::Text { text })) => {
+                Some(PoeEvent::SuggestedReply(text))
+            }
+            (_, Some(ChatResponseData::ToolCalls(tool_calls))) => Some(PoeEvent::Json(json!({
+                "choices": [{ "index": 0, "delta": { "tool_calls": tool_calls } }]
+            }))),
+            (_, Some(ChatResponseData::File(file))) => Some(PoeEvent::File(file)),
+            (_, Some(ChatResponseData::Meta(meta))) => Some(PoeEvent::Meta(meta)),
+            (_, Some(ChatResponseData::Error { text, allow_retry })) => {
+                Some(PoeEvent::Error { text, allow_retry })
+            }
+            (ChatEventType::Json, Some(ChatResponseData::Text { text })) => {
+                match serde_json::from_str(&text) {
+                    Ok(value) => Some(PoeEvent::Json(value)),
+                    Err(_) => Some(PoeEvent::Raw {
+                        name: "json".to_string(),
+                        data: text,
+                    }),
+                }
+            }
+            (_, Some(ChatResponseData::Unknown { name, data })) => {
+                Some(PoeEvent::Raw { name, data })
+            }
+            (ChatEventType::Done, _) => None,
+            _unhandled => {
+                #[cfg(feature = "trace")]
+                warn!("無法轉發的事件: {:?}", _unhandled);
+                None
+            }
+        }
+    }
+}
+
+/// 將另一個 bot 的回應串流轉發為伺服器端事件串流
+///
+/// 子 bot 的 done 事件會被略過，以便在其後繼續輸出；串流錯誤會轉為 error 事件並結束轉發。
+pub fn forward_stream(
+    stream: impl Stream<Item = Result<ChatResponse, PoeError>> + Send + 'static,
+) -> PoeEventStream {
+    forward_stream_with(stream, Some)
+}
+
+/// 同 [`forward_stream`]，並對每個事件套用轉換，返回 None 的事件會被丟棄
+pub fn forward_stream_with<F>(
+    stream: impl Stream<Item = Result<ChatResponse, PoeError>> + Send + 'static,
+    mut transform: F,
+) -> PoeEventStream
+where
+    F: FnMut(PoeEvent) -> Option<PoeEvent> + Send + 'static,
+{
+    let mut failed = false;
+    let events = stream
+        .take_while(move |result| {
+            let keep = !failed;
+            if result.is_err() {
+                failed = true;
+            }
+            futures_util::future::ready(keep)
+        })
+        .filter_map(move |result| {
+            let event = match result {
+                Ok(response) => PoeEvent::from_chat_response(response),
+                Err(e) => {
+                    #[cfg(feature = "trace")]
+                    warn!("轉發的 bot 串流出錯: {}", e);
+                    Some(PoeEvent::error(e.to_string(), false))
+                }
+            };
+            futures_util::future::ready(event)
+        })
+        .filter_map(move |event| {
+            let event = match event {
+                // 錯誤事件不經過轉換，確保能傳回調用方
+                PoeEvent::Error { .. } => Some(event),
+                event => transform(event),
+            };
+            futures_util::future::ready(event)
+        });
+    Box::pin(events)
+}
+
+// 伺服器端 bot trait
+pub trait PoeBot: Send + Sync + 'static {
+    /// 處理 query 請求並返回事件串流，未以 Done 結尾時伺服器會自動補上
+    fn get_response(
+        &self,
+        request: ChatRequest,
+    ) -> impl Future<Output = impl Stream<Item = PoeEvent> + Send + 'static> + Send;
+
+    /// 處理 settings 請求，預設返回空設定
+    fn get_settings(
+        &self,
+        _request: SettingsRequest,
+    ) -> impl Future<Output = SettingsResponse> + Send {
+        async { SettingsResponse::default() }
+    }
+
+    /// 處理用戶對消息的回饋，預設忽略
+    fn on_feedback(&self, _request: ReportFeedbackRequest) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// 處理 Poe 回報的錯誤，預設忽略
+    fn on_error(&self, _request: ReportErrorRequest) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+// 託管 PoeBot 的 HTTP 伺服器
+pub struct PoeBotServer<B: PoeBot> {
+    bot: Arc<B>,
+    access_key: Option<String>,
+    ping_interval: Duration,
+}
+
+struct ServerState<B: PoeBot> {
+    bot: Arc<B>,
+    access_key: Option<String>,
+    ping_interval: Duration,
+}
+
+impl<B: PoeBot> PoeBotServer<B> {
+    /// 建立伺服器，請求必須攜帶與 bot access key 對應的 Bearer token
+    pub fn new(bot: B, access_key: &str) -> Self {
+        Self {
+            bot: Arc::new(bot),
+            access_key: Some(access_key.to_string()),
+            ping_interval: DEFAULT_PING_INTERVAL,
+        }
+    }
+
+    /// 建立不驗證 access key 的伺服器，任何能連線的人都能調用 bot，僅適用於本地測試
+    pub fn without_auth(bot: B) -> Self {
+        Self {
+            bot: Arc::new(bot),
+            access_key: None,
+            ping_interval: DEFAULT_PING_INTERVAL,
+        }
+    }
+
+    /// 設置 SSE ping 間隔
+    pub fn with_ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    /// 建立處理 Poe 請求的路由（接受任意路徑的 POST 請求）
+    pub fn router(self) -> Router {
+        let state = Arc::new(ServerState {
+            bot: self.bot,
+            access_key: self.access_key,
+            ping_interval: self.ping_interval,
+        });
+        Router::new()
+            .route("/", post(handle_request::<B>))
+            .route("/{*path}", post(handle_request::<B>))
+            .with_state(state)
+    }
+
+    /// 在指定地址啟動伺服器
+    pub async fn serve(self, addr: impl ToSocketAddrs) -> Result<(), PoeError> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| PoeError::ServerError(format!("無法綁定地址: {}", e)))?;
+
+        #[cfg(feature = "trace")]
+        debug!("Poe bot 伺服器開始監聽: {:?}", listener.local_addr());
+
+        axum::serve(listener, self.router())
+            .await
+            .map_err(|e| PoeError::ServerError(e.to_string()))
+    }
+}
+
+// 驗證 Authorization 標頭中的 access key，僅 without_auth 建立的伺服器不驗證
+fn is_authorized(headers: &HeaderMap, access_key: Option<&str>) -> bool {
+    let Some(access_key) = access_key else {
+        return true;
+    };
+    bearer_token(headers).is_some_and(|token| constant_time_eq(token, access_key))
+}
+
+// 取得 Authorization 標頭中的 Bearer token
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+// 以固定時間比較密鑰，避免經由回應時間推測密鑰內容
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+async fn handle_request<B: PoeBot>(
+    State(state): State<Arc<ServerState<B>>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !is_authorized(&headers, state.access_key.as_deref()) {
+        #[cfg(feature = "trace")]
+        warn!("收到未授權的請求");
+        return (StatusCode::UNAUTHORIZED, "Invalid access key").into_response();
+    }
+
+    let value: Value = match serde_json::from_slice(&body) {
+        Ok(value) => value,
+        Err(e) => {
+            #[cfg(feature = "trace")]
+            warn!("請求 JSON 解析失敗: {}", e);
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+
+    let request = match PoeRequest::from_value(value) {
+        Ok(request) => request,
+        Err(e) => {
+            #[cfg(feature = "trace")]
+            warn!("請求解析失敗: {}", e);
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+
+    #[cfg(feature = "trace")]
+    debug!("收到 Poe 請求，類型: {}", request.request_type());
+
+    match request {
+        PoeRequest::Query(request) => {
+            let events = state.bot.get_response(request).await;
+            sse_response(events, state.ping_interval)
+        }
+        PoeRequest::Settings(request) => {
+            Json(state.bot.get_settings(request).await).into_response()
+        }
+        PoeRequest::ReportFeedback(request) => {
+            state.bot.on_feedback(request).await;
+            Json(json!({})).into_response()
+        }
+        PoeRequest::ReportError(request) => {
+            #[cfg(feature = "trace")]
+            warn!("Poe 回報錯誤: {}", request.message);
+            state.bot.on_error(request).await;
+            Json(json!({})).into_response()
+        }
+        PoeRequest::Unknown(value) => {
+            let request_type = value
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            (
+                StatusCode::NOT_IMPLEMENTED,
+                format!("Unsupported request type: {}", request_type),
+            )
+                .into_response()
+        }
+    }
+}
+
+// 將事件串流編碼為 SSE 回應，確保以 done 事件結尾
+fn sse_response(
+    events: impl Stream<Item = PoeEvent> + Send + 'static,
+    ping_interval: Duration,
+) -> Response {
+    let mut finished = false;
+    let stream = events
+        .chain(futures_util::stream::once(async { PoeEvent::Done }))
+        .take_while(move |event| {
+            let keep = !finished;
+            if matches!(event, PoeEvent::Done) {
+                finished = true;
+            }
+            futures_util::future::ready(keep)
+        })
+        .map(|event| Ok::<Event, Infallible>(event.to_sse_event()));
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::new().interval(ping_interval).text("ping"))
+        .into_response()
+}
@@ -0,0 +1,708 @@
+use crate::error::PoeError;
+use crate::openai::MAX_TOOL_CALLS;
+use crate::types::*;
+use serde_json::Value;
+#[cfg(feature = "trace")]
+use tracing::{debug, warn};
+
+// 單行 SSE 數據的長度上限，避免遠端持續不送換行導致無上限的緩衝
+pub const MAX_SSE_LINE_BYTES: usize = 4 * 1024 * 1024;
+
+/// 檢查加入 chunk 後尚未以換行結尾的部分是否超過 MAX_SSE_LINE_BYTES
+pub(crate) fn check_pending_line(buffered: usize, chunk: &[u8]) -> Result<(), PoeError> {
+    let pending = match chunk.iter().rposition(|b| *b == b'\n') {
+        Some(pos) => chunk.len() - pos - 1,
+        None => buffered + chunk.len(),
+    };
+    if pending > MAX_SSE_LINE_BYTES {
+        #[cfg(feature = "trace")]
+        warn!("SSE 單行長度超過上限: {} 字節", pending);
+        return Err(PoeError::EventParseFailed(format!(
+            "SSE 單行長度超過上限 {} 字節",
+            MAX_SSE_LINE_BYTES
+        )));
+    }
+    Ok(())
+}
+
+/// 在不超過 max_bytes 的前提下，於字符邊界截斷字串（用於日誌輸出）
+pub fn truncate_str(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+
+    // 從 max_bytes 位置向前查找，直到找到有效的字符邊界
+    let mut end = max_bytes;
+    while end > 0 && !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &s[..end]
+}
+
+// Poe SSE 串流解碼器
+//
+// 以位元組緩衝完整的行後再解碼，因此輸出與網路塊的切分方式無關，
+// 多位元組 UTF-8 字符被切斷在兩個塊之間時也能正確還原。
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    line_buffer: Vec<u8>,
+    current_event: Option<ChatEventType>,
+    // 當前事件的原始名稱，用於傳遞未知事件
+    current_event_name: String,
+    is_collecting_data: bool,
+    // 用於累積 tool_calls 的狀態
+    accumulated_tool_calls: Vec<PartialToolCall>,
+    tool_calls_complete: bool,
+    // XML 工具調用緩衝和檢測狀態
+    #[cfg(feature = "xml")]
+    xml_text_buffer: String,
+    #[cfg(feature = "xml")]
+    xml_detection_active: bool,
+    #[cfg(feature = "xml")]
+    available_tools: Vec<ChatTool>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 設置用於檢測 XML 工具調用的工具定義
+    #[cfg(feature = "xml")]
+    pub fn with_tools(mut self, tools: Vec<ChatTool>) -> Self {
+        self.available_tools = tools;
+        self
+    }
+
+    /// 輸入一個網路塊，返回其中完整行解碼出的事件
+    ///
+    /// 未以換行結尾的部分超過 MAX_SSE_LINE_BYTES 時清空緩衝並返回 EventParseFailed。
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<ChatResponse>, PoeError> {
+        #[cfg(feature = "trace")]
+        debug!("處理串流塊，大小: {} 字節", chunk.len());
+
+        if let Err(e) = check_pending_line(self.line_buffer.len(), chunk) {
+            self.line_buffer.clear();
+            return Err(e);
+        }
+
+        let mut events = Vec::new();
+        // 將新的塊添加到緩衝區
+        self.line_buffer.extend_from_slice(chunk);
+
+        // 尋找完整的行
+        while let Some(newline_pos) = self.line_buffer.iter().position(|b| *b == b'\n') {
+            let raw_line: Vec<u8> = self.line_buffer.drain(..=newline_pos).collect();
+            let line = String::from_utf8_lossy(&raw_line[..newline_pos])
+                .trim()
+                .to_string();
+            self.process_line(&line, &mut events);
+        }
+
+        Ok(events)
+    }
+
+    /// 串流結束時處理緩衝區中未以換行結尾的最後一行
+    pub fn finish(&mut self) -> Vec<ChatResponse> {
+        let mut events = Vec::new();
+        if !self.line_buffer.is_empty() {
+            let raw_line = std::mem::take(&mut self.line_buffer);
+            let line = String::from_utf8_lossy(&raw_line).trim().to_string();
+            self.process_line(&line, &mut events);
+        }
+        events
+    }
+
+    fn process_line(&mut self, line: &str, events: &mut Vec<ChatResponse>) {
+        if line.is_empty() {
+            // 重置當前事件狀態，準備處理下一個事件
+            self.current_event = None;
+            self.is_collecting_data = false;
+            return;
+        }
+
+        if line == ": ping" {
+            #[cfg(feature = "trace")]
+            debug!("收到 ping 訊號");
+            return;
+        }
+
+        if line.starts_with("event: ") {
+            let event_name = line.trim_start_matches("event: ").trim();
+            #[cfg(feature = "trace")]
+            debug!("解析事件類型: {}", event_name);
+
+            let event_type = match event_name {
+                "text" => ChatEventType::Text,
+                "replace_response" => ChatEventType::ReplaceResponse,
+                "json" => ChatEventType::Json,
+                "file" => ChatEventType::File,
+                "done" => ChatEventType::Done,
+                "error" => ChatEventType::Error,
+                "meta" => ChatEventType::Meta,
+                "suggested_reply" => ChatEventType::SuggestedReply,
+                _ => {
+                    #[cfg(feature = "trace")]
+                    warn!("收到未知事件類型: {}", event_name);
+                    ChatEventType::Unknown
+                }
+            };
+
+            self.current_event_name = event_name.to_string();
+            self.current_event = Some(event_type);
+            self.is_collecting_data = false;
+            return;
+        }
+
+        if line.starts_with("data: ") {
+            let data = line.trim_start_matches("data: ").trim();
+            #[cfg(feature = "trace")]
+            debug!("收到事件數據: {}", truncate_str(data, 100));
+
+            if let Some(event_type) = self.current_event.clone() {
+                self.handle_data(event_type, data, events);
+            } else {
+                #[cfg(feature = "trace")]
+                debug!("收到數據但沒有當前事件類型");
+            }
+        } else if self.is_collecting_data {
+            // 嘗試解析累積的 JSON
+            #[cfg(feature = "trace")]
+            debug!("嘗試解析未完整的 JSON 數據: {}", truncate_str(line, 100));
+
+            if let Some(event_type) = self.current_event.clone() {
+                self.handle_collected(event_type, line, events);
+            }
+        }
+
+        // 每行處理完後檢查是否需要發送完整的 tool_calls 事件
+        self.flush_tool_calls(events);
+    }
+
+    fn handle_data(
+        &mut self,
+        event_type: ChatEventType,
+        data: &str,
+        events: &mut Vec<ChatResponse>,
+    ) {
+        match event_type {
+            ChatEventType::Text | ChatEventType::ReplaceResponse => {
+                if let Ok(json) = serde_json::from_str::<Value>(data) {
+                    if let Some(text) = json.get("text").and_then(Value::as_str) {
+                        #[cfg(feature = "trace")]
+                        debug!("解析到文本數據，長度: {}", text.len());
+
+                        #[cfg(feature = "xml")]
+                        self.handle_xml_text(event_type, text, events);
+
+                        #[cfg(not(feature = "xml"))]
+                        events.push(ChatResponse {
+                            event: event_type,
+                            data: Some(ChatResponseData::Text {
+                                text: text.to_string(),
+                            }),
+                        });
+                    }
+                } else {
+                    #[cfg(feature = "trace")]
+                    debug!("JSON 解析失敗，可能是不完整的數據，等待更多數據");
+                    self.is_collecting_data = true;
+                }
+            }
+            ChatEventType::File => {
+                if let Ok(file_data) = serde_json::from_str::<FileData>(data) {
+                    #[cfg(feature = "trace")]
+                    debug!("解析到文件數據: {}", file_data.name);
+                    events.push(ChatResponse {
+                        event: ChatEventType::File,
+                        data: Some(ChatResponseData::File(file_data)),
+                    });
+                } else {
+                    #[cfg(feature = "trace")]
+                    debug!("文件數據 JSON 解析失敗，可能是不完整的數據，等待更多數據");
+                    self.is_collecting_data = true;
+                }
+            }
+            ChatEventType::Json => {
+                if let Ok(json) = serde_json::from_str::<Value>(data) {
+                    #[cfg(feature = "trace")]
+                    debug!("解析到 JSON 事件數據");
+                    let has_tool_calls = self.apply_tool_call_deltas(&json);
+                    if !has_tool_calls && !self.tool_calls_complete {
+                        // 如果沒有 tool_calls delta 且工具調用尚未完成，
+                        // 則按一般 JSON 處理
+                        events.push(ChatResponse {
+                            event: ChatEventType::Json,
+                            data: Some(ChatResponseData::Text {
+                                text: data.to_string(),
+                            }),
+                        });
+                    }
+                } else {
+                    #[cfg(feature = "trace")]
+                    debug!("JSON 事件解析失敗，可能是不完整的數據");
+                    self.is_collecting_data = true;
+                }
+            }
+            ChatEventType::Done => {
+                #[cfg(feature = "trace")]
+                debug!("收到完成事件");
+                // 處理任何剩餘的 XML 緩衝內容
+                #[cfg(feature = "xml")]
+                self.flush_xml_buffer(events);
+                events.push(ChatResponse {
+                    event: ChatEventType::Done,
+                    data: Some(ChatResponseData::Empty),
+                });
+                self.current_event = None;
+            }
+            ChatEventType::Error => {
+                if let Ok(json) = serde_json::from_str::<Value>(data) {
+                    let text = json
+                        .get("text")
+                        .and_then(Value::as_str)
+                        .unwrap_or("未知錯誤");
+                    let allow_retry = json
+                        .get("allow_retry")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(false);
+
+                    #[cfg(feature = "trace")]
+                    warn!("收到錯誤事件: {}, 可重試: {}", text, allow_retry);
+
+                    events.push(ChatResponse {
+                        event: ChatEventType::Error,
+                        data: Some(ChatResponseData::Error {
+                            text: text.to_string(),
+                            allow_retry,
+                        }),
+                    });
+                } else {
+                    #[cfg(feature = "trace")]
+                    warn!("無法解析錯誤事件數據: {}", truncate_str(data, 100));
+                }
+                self.current_event = None;
+            }
+            ChatEventType::Meta => {
+                if let Ok(meta) = serde_json::from_str::<MetaData>(data) {
+                    #[cfg(feature = "trace")]
+                    debug!("解析到 meta 數據: {:?}", meta);
+                    events.push(ChatResponse {
+                        event: ChatEventType::Meta,
+                        data: Some(ChatResponseData::Meta(meta)),
+                    });
+                    self.current_event = None;
+                } else {
+                    #[cfg(feature = "trace")]
+                    debug!("meta 數據解析失敗，可能是不完整的數據，等待更多數據");
+                    self.is_collecting_data = true;
+                }
+            }
+            ChatEventType::SuggestedReply => {
+                if let Ok(json) = serde_json::from_str::<Value>(data) {
+                    if let Some(text) = json.get("text").and_then(Value::as_str) {
+                        #[cfg(feature = "trace")]
+                        debug!("解析到建議回覆: {}", text);
+                        events.push(ChatResponse {
+                            event: ChatEventType::SuggestedReply,
+                            data: Some(ChatResponseData::Text {
+                                text: text.to_string(),
+                            }),
+                        });
+                    }
+                    self.current_event = None;
+                } else {
+                    #[cfg(feature = "trace")]
+                    debug!("建議回覆解析失敗，可能是不完整的數據，等待更多數據");
+                    self.is_collecting_data = true;
+                }
+            }
+            ChatEventType::Unknown => {
+                // 未知事件原樣傳遞，避免遺失新的伺服器事件
+                events.push(ChatResponse {
+                    event: ChatEventType::Unknown,
+                    data: Some(ChatResponseData::Unknown {
+                        name: self.current_event_name.clone(),
+                        data: data.to_string(),
+                    }),
+                });
+            }
+        }
+    }
+
+    fn handle_collected(
+        &mut self,
+        event_type: ChatEventType,
+        line: &str,
+        events: &mut Vec<ChatResponse>,
+    ) {
+        match event_type {
+            ChatEventType::Text | ChatEventType::ReplaceResponse => {
+                if let Ok(json) = serde_json::from_str::<Value>(line)
+                    && let Some(text) = json.get("text").and_then(Value::as_str)
+                {
+                    #[cfg(feature = "trace")]
+                    debug!("成功解析到累積的 JSON 文本，長度: {}", text.len());
+
+                    events.push(ChatResponse {
+                        event: event_type,
+                        data: Some(ChatResponseData::Text {
+                            text: text.to_string(),
+                        }),
+                    });
+                    self.is_collecting_data = false;
+                    self.current_event = None;
+                }
+            }
+            ChatEventType::File => {
+                if let Ok(file_data) = serde_json::from_str::<FileData>(line) {
+                    #[cfg(feature = "trace")]
+                    debug!("成功解析到累積的文件數據: {}", file_data.name);
+
+                    events.push(ChatResponse {
+                        event: ChatEventType::File,
+                        data: Some(ChatResponseData::File(file_data)),
+                    });
+                    self.is_collecting_data = false;
+                    self.current_event = None;
+                }
+            }
+            ChatEventType::Json => {
+                if let Ok(json) = serde_json::from_str::<Value>(line) {
+                    #[cfg(feature = "trace")]
+                    debug!("成功解析到累積的 JSON 事件數據");
+
+                    if !self.apply_tool_call_deltas(&json) {
+                        // 如果沒有 tool_calls delta，則按一般 JSON 處理
+                        events.push(ChatResponse {
+                            event: ChatEventType::Json,
+                            data: Some(ChatResponseData::Text {
+                                text: line.to_string(),
+                            }),
+                        });
+                    }
+
+                    self.is_collecting_data = false;
+                    self.current_event = None;
+                }
+            }
+            ChatEventType::Meta => {
+                if let Ok(meta) = serde_json::from_str::<MetaData>(line) {
+                    #[cfg(feature = "trace")]
+                    debug!("成功解析到累積的 meta 數據");
+
+                    events.push(ChatResponse {
+                        event: ChatEventType::Meta,
+                        data: Some(ChatResponseData::Meta(meta)),
+                    });
+                    self.is_collecting_data = false;
+                    self.current_event = None;
+                }
+            }
+            ChatEventType::SuggestedReply => {
+                if let Ok(json) = serde_json::from_str::<Value>(line) {
+                    if let Some(text) = json.get("text").and_then(Value::as_str) {
+                        #[cfg(feature = "trace")]
+                        debug!("成功解析到累積的建議回覆");
+
+                        events.push(ChatResponse {
+                            event: ChatEventType::SuggestedReply,
+                            data: Some(ChatResponseData::Text {
+                                text: text.to_string(),
+                            }),
+                        });
+                    }
+                    self.is_collecting_data = false;
+                    self.current_event = None;
+                }
+            }
+            ChatEventType::Done | ChatEventType::Error | ChatEventType::Unknown => {
+                // 這些事件類型不應該有累積的數據
+                self.is_collecting_data = false;
+            }
+        }
+    }
+
+    // 累積 OpenAI 格式的 tool_calls delta，返回數據中是否包含 tool_calls
+    fn apply_tool_call_deltas(&mut self, json: &Value) -> bool {
+        let choice = json.get("choices").and_then(|choices| choices.get(0));
+
+        // 檢查是否有 finish_reason: "tool_calls"，表示工具調用完成
+        let finish_reason = choice
+            .and_then(|choice| choice.get("finish_reason"))
+            .and_then(Value::as_str);
+        if finish_reason == Some("tool_calls") {
+            #[cfg(feature = "trace")]
+            debug!("檢測到工具調用完成標誌");
+            self.tool_calls_complete = true;
+        }
+
+        // 檢查是否包含 tool_calls delta
+        let Some(tool_calls_array) = choice
+            .and_then(|choice| choice.get("delta"))
+            .and_then(|delta| delta.get("tool_calls"))
+        else {
+            return false;
+        };
+
+        #[cfg(feature = "trace")]
+        debug!("檢測到工具調用 delta");
+
+        // 處理每個工具調用的 delta
+        if let Some(tool_calls) = tool_calls_array.as_array() {
+            for tool_call_delta in tool_calls {
+                // 缺少 index 時視為第一個工具調用，非整數的 index 視為無效
+                let index = match tool_call_delta.get("index") {
+                    Some(index) => index.as_u64().unwrap_or(u64::MAX),
+                    None => 0,
+                };
+
+                // 忽略超出上限的 index，避免遠端內容導致無上限的記憶體分配
+                if index >= MAX_TOOL_CALLS as u64 {
+                    #[cfg(feature = "trace")]
+                    warn!("忽略超出上限的工具調用 index: {}", index);
+                    continue;
+                }
+                let index = index as usize;
+
+                // 確保 accumulated_tool_calls 有足夠的元素
+                while self.accumulated_tool_calls.len() <= index {
+                    self.accumulated_tool_calls.push(PartialToolCall::default());
+                }
+                let partial = &mut self.accumulated_tool_calls[index];
+
+                // 更新 id 和 type
+                if let Some(id) = tool_call_delta.get("id").and_then(Value::as_str) {
+                    partial.id = id.to_string();
+                }
+                if let Some(type_str) = tool_call_delta.get("type").and_then(Value::as_str) {
+                    partial.r#type = type_str.to_string();
+                }
+
+                // 更新 function 相關欄位
+                if let Some(function) = tool_call_delta.get("function") {
+                    if let Some(name) = function.get("name").and_then(Value::as_str) {
+                        partial.function_name = name.to_string();
+                    }
+                    if let Some(args) = function.get("arguments").and_then(Value::as_str) {
+                        partial.function_arguments.push_str(args);
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    // 工具調用完成時發送完整的 tool_calls 事件
+    fn flush_tool_calls(&mut self, events: &mut Vec<ChatResponse>) {
+        if !self.tool_calls_complete || self.accumulated_tool_calls.is_empty() {
+            return;
+        }
+
+        let complete_tool_calls = self
+            .accumulated_tool_calls
+            .iter()
+            .filter(|tc| !tc.id.is_empty() && !tc.function_name.is_empty())
+            .map(|tc| ChatToolCall {
+                id: tc.id.clone(),
+                r#type: tc.r#type.clone(),
+                function: FunctionCall {
+                    name: tc.function_name.clone(),
+                    arguments: tc.function_arguments.clone(),
+                },
+            })
+            .collect::<Vec<ChatToolCall>>();
+
+        if !complete_tool_calls.is_empty() {
+            #[cfg(feature = "trace")]
+            debug!("發送完整的工具調用，數量: {}", complete_tool_calls.len());
+
+            events.push(ChatResponse {
+                event: ChatEventType::Json,
+                data: Some(ChatResponseData::ToolCalls(complete_tool_calls)),
+            });
+
+            // 重置累積狀態
+            self.accumulated_tool_calls.clear();
+            self.tool_calls_complete = false;
+        }
+    }
+
+    // XML 工具調用檢測和緩衝邏輯
+    #[cfg(feature = "xml")]
+    fn handle_xml_text(
+        &mut self,
+        event_type: ChatEventType,
+        text: &str,
+        events: &mut Vec<ChatResponse>,
+    ) {
+        // 基於實際工具定義的智能檢測
+        let should_start_xml_detection = !self.xml_detection_active
+            && (text.contains("<tool_call>")
+                || text.contains("<invoke")
+                // 檢查是否包含任何已定義的工具名稱標籤
+                || self
+                    .available_tools
+                    .iter()
+                    .any(|tool| text.contains(&format!("<{}>", tool.function.name))));
+        if should_start_xml_detection {
+            self.xml_detection_active = true;
+            self.xml_text_buffer.clear();
+            #[cfg(feature = "trace")]
+            debug!("檢測到已定義工具的 XML 調用，開始 XML 緩衝 | 清空緩衝區重新開始");
+        }
+
+        if !self.xml_detection_active {
+            // 沒有檢測到 XML，直接發送文本
+            events.push(ChatResponse {
+                event: event_type,
+                data: Some(ChatResponseData::Text {
+                    text: text.to_string(),
+                }),
+            });
+            return;
+        }
+
+        self.xml_text_buffer.push_str(text);
+        #[cfg(feature = "trace")]
+        debug!(
+            "XML 模式：文本已添加到緩衝區 | 長度: {}",
+            self.xml_text_buffer.len()
+        );
+
+        // 檢查是否有完整的工具調用
+        let message = ChatMessage {
+            role: Role::Bot,
+            content: self.xml_text_buffer.clone(),
+            attachments: None,
+            content_type: ContentType::Plain,
+            ..Default::default()
+        };
+        // 使用工具定義來檢測和解析
+        if message.contains_xml_tool_calls_with_tools(&self.available_tools) {
+            let tool_calls = message.extract_xml_tool_calls_with_tools(&self.available_tools);
+            if !tool_calls.is_empty() {
+                #[cfg(feature = "trace")]
+                debug!(
+                    "檢測到完整的 XML 工具調用，轉換為標準格式，數量: {}",
+                    tool_calls.len()
+                );
+                // 發送工具調用事件
+                events.push(ChatResponse {
+                    event: ChatEventType::Json,
+                    data: Some(ChatResponseData::ToolCalls(tool_calls)),
+                });
+                // 移除 XML 部分並發送剩餘文本
+                let clean_text =
+                    crate::client::PoeClient::remove_xml_tool_calls(&self.xml_text_buffer);
+                if !clean_text.trim().is_empty() {
+                    events.push(ChatResponse {
+                        event: event_type,
+                        data: Some(ChatResponseData::Text { text: clean_text }),
+                    });
+                }
+                // 重置 XML 緩衝狀態
+                self.xml_text_buffer.clear();
+                self.xml_detection_active = false;
+            } else {
+                // 沒有完整的工具調用，繼續緩衝
+                #[cfg(feature = "trace")]
+                debug!("XML 工具調用尚未完整，繼續緩衝");
+            }
+        } else {
+            // 檢查是否應該釋放緩衝區
+            let should_release = self.xml_text_buffer.contains('\n')
+                && self.xml_text_buffer.len() > 200
+                && !self.available_tools.iter().any(|tool| {
+                    self.xml_text_buffer
+                        .contains(&format!("<{}>", tool.function.name))
+                        || self
+                            .xml_text_buffer
+                            .contains(&format!("</{}>", tool.function.name))
+                })
+                && !self.xml_text_buffer.contains("<tool_call>")
+                && !self.xml_text_buffer.contains("<invoke");
+            if should_release {
+                #[cfg(feature = "trace")]
+                debug!("XML 緩衝區過大或不包含工具調用，發送為普通文本");
+                // 發送緩衝的文本
+                events.push(ChatResponse {
+                    event: event_type,
+                    data: Some(ChatResponseData::Text {
+                        text: self.xml_text_buffer.clone(),
+                    }),
+                });
+                // 重置緩衝狀態
+                self.xml_text_buffer.clear();
+                self.xml_detection_active = false;
+            } else {
+                // 繼續緩衝
+                #[cfg(feature = "trace")]
+                debug!(
+                    "繼續緩衝 XML 文本，當前長度: {}",
+                    self.xml_text_buffer.len()
+                );
+            }
+        }
+    }
+
+    // 完成事件時處理剩餘的 XML 緩衝內容
+    #[cfg(feature = "xml")]
+    fn flush_xml_buffer(&mut self, events: &mut Vec<ChatResponse>) {
+        if !self.xml_detection_active || self.xml_text_buffer.trim().is_empty() {
+            return;
+        }
+
+        #[cfg(feature = "trace")]
+        debug!(
+            "處理剩餘的 XML 緩衝內容，長度: {}",
+            self.xml_text_buffer.len()
+        );
+        let message = ChatMessage {
+            role: Role::Bot,
+            content: self.xml_text_buffer.clone(),
+            attachments: None,
+            content_type: ContentType::Plain,
+            ..Default::default()
+        };
+        // 使用工具定義來檢測和解析
+        let tool_calls = if message.contains_xml_tool_calls_with_tools(&self.available_tools) {
+            message.extract_xml_tool_calls_with_tools(&self.available_tools)
+        } else {
+            Vec::new()
+        };
+
+        if !tool_calls.is_empty() {
+            #[cfg(feature = "trace")]
+            debug!(
+                "在完成事件中檢測到 XML 工具調用，數量: {}",
+                tool_calls.len()
+            );
+            // 發送工具調用事件
+            events.push(ChatResponse {
+                event: ChatEventType::Json,
+                data: Some(ChatResponseData::ToolCalls(tool_calls)),
+            });
+            // 發送清理後的文本（如果有）
+            let clean_text = crate::client::PoeClient::remove_xml_tool_calls(&self.xml_text_buffer);
+            if !clean_text.trim().is_empty() {
+                events.push(ChatResponse {
+                    event: ChatEventType::Text,
+                    data: Some(ChatResponseData::Text { text: clean_text }),
+                });
+            }
+        } else {
+            // 發送為普通文本
+            events.push(ChatResponse {
+                event: ChatEventType::Text,
+                data: Some(ChatResponseData::Text {
+                    text: self.xml_text_buffer.clone(),
+                }),
+            });
+        }
+        // 清理緩衝狀態
+        self.xml_text_buffer.clear();
+        self.xml_detection_active = false;
+    }
+}
@@ -0,0 +1,557 @@
+use crate::client::PoeClient;
+use crate::error::PoeError;
+use crate::types::*;
+use axum::Router;
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header::AUTHORIZATION};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{get, post};
+use futures_util::StreamExt;
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+#[cfg(feature = "trace")]
+use tracing::debug;
+
+// 模擬伺服器的檔案上傳路徑
+pub const MOCK_UPLOAD_PATH: &str = "/file_upload";
+// 模擬伺服器的 GraphQL 路徑
+pub const MOCK_GQL_PATH: &str = "/api/gql_POST";
+// 模擬伺服器提供 bot 產生檔案的路徑前綴
+pub const MOCK_FILES_PATH: &str = "/files";
+
+// 模擬伺服器的端點
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockEndpoint {
+    Bot,
+    Models,
+    Upload,
+    Gql,
+    File,
+}
+
+// 串流腳本中的單個步驟
+#[derive(Debug, Clone)]
+pub enum MockStep {
+    // 完整的 SSE 事件
+    Event { name: String, data: String },
+    // 原樣輸出的位元組
+    Raw(Bytes),
+    // 在輸出下一個步驟前等待
+    Delay(Duration),
+}
+
+// bot 端點的回應腳本
+#[derive(Debug, Clone, Default)]
+pub struct MockScript {
+    steps: Vec<MockStep>,
+    chunk_size: Option<usize>,
+    status: Option<(u16, String)>,
+}
+
+impl MockScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 單段文本回覆並以 done 結尾
+    pub fn reply(text: &str) -> Self {
+        Self::new().text(text).done()
+    }
+
+    /// 以指定狀態碼及內容回應，不輸出串流
+    pub fn failure(status: u16, body: &str) -> Self {
+        Self {
+            status: Some((status, body.to_string())),
+            ..Default::default()
+        }
+    }
+
+    /// 添加任意 SSE 事件
+    pub fn event(mut self, name: &str, data: &str) -> Self {
+        self.steps.push(MockStep::Event {
+            name: name.to_string(),
+            data: data.to_string(),
+        });
+        self
+    }
+
+    pub fn text(self, text: &str) -> Self {
+        let data = json!({ "text": text }).to_string();
+        self.event("text", &data)
+    }
+
+    pub fn replace_response(self, text: &str) -> Self {
+        let data = json!({ "text": text }).to_string();
+        self.event("replace_response", &data)
+    }
+
+    pub fn suggested_reply(self, text: &str) -> Self {
+        let data = json!({ "text": text }).to_string();
+        self.event("suggested_reply", &data)
+    }
+
+    pub fn json(self, value: Value) -> Self {
+        self.event("json", &value.to_string())
+    }
+
+    /// 以 OpenAI 增量格式輸出完整的工具調用
+    pub fn tool_calls(self, tool_calls: &[ChatToolCall]) -> Self {
+        let deltas: Vec<Value> = tool_calls
+            .iter()
+            .enumerate()
+            .map(|(index, call)| {
+                json!({
+                    "index": index,
+                    "id": call.id,
+                    "type": call.r#type,
+                    "function": {
+                        "name": call.function.name,
+                        "arguments": call.function.arguments,
+                    }
+                })
+            })
+            .collect();
+        self.json(json!({
+            "choices": [{ "index": 0, "delta": { "tool_calls": deltas }, "finish_reason": "tool_calls" }]
+        }))
+    }
+
+    pub fn meta(self, meta: &MetaData) -> Self {
+        let data = serde_json::to_string(meta).unwrap_or_default();
+        self.event("meta", &data)
+    }
+
+    pub fn file(self, file: &FileData) -> Self {
+        let data = serde_json::to_string(file).unwrap_or_default();
+        self.event("file", &data)
+    }
+
+    pub fn error(self, text: &str, allow_retry: bool) -> Self {
+        let data = json!({ "text": text, "allow_retry": allow_retry }).to_string();
+        self.event("error", &data)
+    }
+
+    pub fn done(self) -> Self {
+        self.event("done", "{}")
+    }
+
+    /// 添加原樣輸出的內容，可用於構造格式錯誤的串流
+    pub fn raw(mut self, raw: impl Into<Bytes>) -> Self {
+        self.steps.push(MockStep::Raw(raw.into()));
+        self
+    }
+
+    /// 在下一個步驟前等待
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.steps.push(MockStep::Delay(delay));
+        self
+    }
+
+    /// 將每個步驟的輸出再切分為指定大小的塊（可能切斷 UTF-8 字符）
+    pub fn split_every(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size.max(1));
+        self
+    }
+
+    /// 展開為帶延遲的輸出塊
+    pub fn frames(&self) -> Vec<(Duration, Bytes)> {
+        let mut frames = Vec::new();
+        let mut pending_delay = Duration::ZERO;
+        for step in &self.steps {
+            let bytes = match step {
+                MockStep::Delay(delay) => {
+                    pending_delay += *delay;
+                    continue;
+                }
+                MockStep::Event { name, data } => {
+                    Bytes::from(format!("event: {}\ndata: {}\n\n", name, data))
+                }
+                MockStep::Raw(raw) => raw.clone(),
+            };
+            match self.chunk_size {
+                Some(chunk_size) => {
+                    for (index, piece) in bytes.chunks(chunk_size).enumerate() {
+                        let delay = if index == 0 {
+                            pending_delay
+                        } else {
+                            Duration::ZERO
+                        };
+                        frames.push((delay, Bytes::copy_from_slice(piece)));
+                    }
+                }
+                None => frames.push((pending_delay, bytes)),
+            }
+            pending_delay = Duration::ZERO;
+        }
+        frames
+    }
+}
+
+// 模擬伺服器收到的請求
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub endpoint: MockEndpoint,
+    pub path: String,
+    pub authorization: Option<String>,
+    pub body: Bytes,
+}
+
+impl RecordedRequest {
+    /// 將請求內容解析為 JSON
+    pub fn json(&self) -> Result<Value, PoeError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    base_url: String,
+    access_key: Option<String>,
+    scripts: HashMap<String, MockScript>,
+    queued_scripts: HashMap<String, VecDeque<MockScript>>,
+    models: Vec<String>,
+    failures: HashMap<MockEndpoint, VecDeque<(u16, String)>>,
+    requests: Vec<RecordedRequest>,
+    upload_count: usize,
+    files: HashMap<String, (String, Bytes)>,
+}
+
+// 用於離線測試的模擬 Poe 伺服器，在本地隨機端口提供 bot、模型列表、檔案上傳及 GraphQL 端點
+pub struct MockPoeServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockPoeServer {
+    /// 在 127.0.0.1 的隨機端口啟動模擬伺服器
+    pub async fn start() -> Result<Self, PoeError> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .map_err(|e| PoeError::ServerError(format!("無法綁定地址: {}", e)))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|e| PoeError::ServerError(e.to_string()))?;
+
+        let state = Arc::new(Mutex::new(MockState {
+            base_url: format!("http://{}", addr),
+            models: vec!["Claude-3.7-Sonnet".to_string(), "GPT-4o".to_string()],
+            ..Default::default()
+        }));
+        let router = Router::new()
+            .route("/bot/{name}", post(handle_bot))
+            .route("/v1/models", get(handle_models))
+            .route(MOCK_UPLOAD_PATH, post(handle_upload))
+            .route(MOCK_GQL_PATH, post(handle_gql))
+            .route(&format!("{}/{{name}}", MOCK_FILES_PATH), get(handle_file))
+            .with_state(state.clone());
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(listener, router).await;
+        });
+
+        #[cfg(feature = "trace")]
+        debug!("模擬 Poe 伺服器已啟動: {}", addr);
+
+        Ok(Self {
+            addr,
+            state,
+            handle,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn upload_url(&self) -> String {
+        format!("{}{}", self.base_url(), MOCK_UPLOAD_PATH)
+    }
+
+    pub fn gql_url(&self) -> String {
+        format!("{}{}", self.base_url(), MOCK_GQL_PATH)
+    }
+
+    /// 建立指向模擬伺服器的客戶端
+    pub fn client(&self, bot_name: &str) -> PoeClient {
+        PoeClient::new(bot_name, "test-key", &self.base_url(), &self.upload_url())
+    }
+
+    /// 提供一個可下載的檔案，返回對應的 File 事件數據
+    pub fn serve_file(
+        &self,
+        name: &str,
+        content_type: &str,
+        data: impl Into<Bytes>,
+        inline_ref: &str,
+    ) -> FileData {
+        self.lock()
+            .files
+            .insert(name.to_string(), (content_type.to_string(), data.into()));
+        FileData {
+            url: format!("{}{}/{}", self.base_url(), MOCK_FILES_PATH, name),
+            name: name.to_string(),
+            content_type: content_type.to_string(),
+            inline_ref: inline_ref.to_string(),
+        }
+    }
+
+    /// 要求請求攜帶指定的 Bearer token，否則返回 401
+    pub fn require_access_key(&self, access_key: &str) -> &Self {
+        self.lock().access_key = Some(access_key.to_string());
+        self
+    }
+
+    /// 設置 bot 的預設腳本，未設置時 bot 會回顯最後一條消息
+    pub fn script_bot(&self, bot_name: &str, script: MockScript) -> &Self {
+        self.lock().scripts.insert(bot_name.to_string(), script);
+        self
+    }
+
+    /// 排隊一次性腳本，優先於預設腳本，按順序用於後續請求
+    pub fn enqueue_script(&self, bot_name: &str, script: MockScript) -> &Self {
+        self.lock()
+            .queued_scripts
+            .entry(bot_name.to_string())
+            .or_default()
+            .push_back(script);
+        self
+    }
+
+    /// 設置 v1/models 及 GraphQL 端點返回的模型
+    pub fn set_models(&self, models: &[&str]) -> &Self {
+        self.lock().models = models.iter().map(|model| model.to_string()).collect();
+        self
+    }
+
+    /// 令指定端點的下一次請求以給定狀態碼失敗
+    pub fn fail_next(&self, endpoint: MockEndpoint, status: u16, body: &str) -> &Self {
+        self.lock()
+            .failures
+            .entry(endpoint)
+            .or_default()
+            .push_back((status, body.to_string()));
+        self
+    }
+
+    /// 已收到的所有請求
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// 指定 bot 收到的請求
+    pub fn bot_requests(&self, bot_name: &str) -> Vec<ChatRequest> {
+        let path = format!("/bot/{}", bot_name);
+        self.lock()
+            .requests
+            .iter()
+            .filter(|request| request.path == path)
+            .filter_map(|request| serde_json::from_slice(&request.body).ok())
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for MockPoeServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+// 記錄請求並檢查授權及注入的錯誤，需要提前回應時返回 Some
+fn begin_request(
+    state: &SharedState,
+    endpoint: MockEndpoint,
+    path: String,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Option<Response> {
+    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+    let authorization = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    state.requests.push(RecordedRequest {
+        endpoint,
+        path,
+        authorization: authorization.clone(),
+        body,
+    });
+
+    // 檔案地址與真實 CDN 一樣無需授權
+    if endpoint != MockEndpoint::File
+        && let Some(ref access_key) = state.access_key
+    {
+        let token = authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if token != Some(access_key.as_str()) {
+            return Some((StatusCode::UNAUTHORIZED, "Invalid access key").into_response());
+        }
+    }
+
+    if let Some((status, body)) = state
+        .failures
+        .get_mut(&endpoint)
+        .and_then(VecDeque::pop_front)
+    {
+        return Some(status_response(status, body));
+    }
+    None
+}
+
+fn status_response(status: u16, body: String) -> Response {
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (status, body).into_response()
+}
+
+async fn handle_bot(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let path = format!("/bot/{}", name);
+    if let Some(response) = begin_request(&state, MockEndpoint::Bot, path, &headers, body.clone()) {
+        return response;
+    }
+
+    let script = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state
+            .queued_scripts
+            .get_mut(&name)
+            .and_then(VecDeque::pop_front)
+            .or_else(|| state.scripts.get(&name).cloned())
+    };
+    let script = script.unwrap_or_else(|| {
+        // 未設置腳本時回顯最後一條消息
+        let content = serde_json::from_slice::<ChatRequest>(&body)
+            .ok()
+            .and_then(|request| request.query.last().map(|message| message.content.clone()))
+            .unwrap_or_default();
+        MockScript::reply(&format!("Echo: {}", content))
+    });
+
+    if let Some((status, body)) = script.status.clone() {
+        return status_response(status, body);
+    }
+
+    let frames = script.frames();
+    let stream = futures_util::stream::iter(frames).then(|(delay, bytes)| async move {
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        Ok::<Bytes, Infallible>(bytes)
+    });
+    Response::builder()
+        .header("content-type", "text/event-stream")
+        .body(Body::from_stream(stream))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+async fn handle_models(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    let path = "/v1/models".to_string();
+    if let Some(response) =
+        begin_request(&state, MockEndpoint::Models, path, &headers, Bytes::new())
+    {
+        return response;
+    }
+    let models = state
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .models
+        .clone();
+    let data: Vec<Value> = models
+        .iter()
+        .map(|id| json!({ "id": id, "object": "model", "created": 0, "owned_by": "poe" }))
+        .collect();
+    Json(json!({ "object": "list", "data": data })).into_response()
+}
+
+async fn handle_upload(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let path = MOCK_UPLOAD_PATH.to_string();
+    let size = body.len() as u64;
+    let mime_type = multipart_content_type(&body);
+    if let Some(response) = begin_request(&state, MockEndpoint::Upload, path, &headers, body) {
+        return response;
+    }
+    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+    state.upload_count += 1;
+    Json(FileUploadResponse {
+        attachment_url: format!("{}/files/{}", state.base_url, state.upload_count),
+        mime_type,
+        size: Some(size),
+    })
+    .into_response()
+}
+
+// 與真實 API 一樣返回檔案部分的 Content-Type，遠端檔案沒有此標頭
+fn multipart_content_type(body: &[u8]) -> Option<String> {
+    let header = b"Content-Type: ";
+    let start = body
+        .windows(header.len())
+        .position(|window| window == header)?
+        + header.len();
+    let end = start + body[start..].iter().position(|&byte| byte == b'\r')?;
+    String::from_utf8(body[start..end].to_vec()).ok()
+}
+
+async fn handle_file(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let path = format!("{}/{}", MOCK_FILES_PATH, name);
+    if let Some(response) = begin_request(&state, MockEndpoint::File, path, &headers, Bytes::new())
+    {
+        return response;
+    }
+    let file = state
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .files
+        .get(&name)
+        .cloned();
+    match file {
+        Some((content_type, data)) => {
+            ([(axum::http::header::CONTENT_TYPE, content_type)], data).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn handle_gql(State(state): State<SharedState>, headers: HeaderMap, body: Bytes) -> Response {
+    let path = MOCK_GQL_PATH.to_string();
+    if let Some(response) = begin_request(&state, MockEndpoint::Gql, path, &headers, body) {
+        return response;
+    }
+    let models = state
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .models
+        .clone();
+    let edges: Vec<Value> = models
+        .iter()
+        .map(|handle| json!({ "node": { "handle": handle } }))
+        .collect();
+    Json(json!({ "data": { "exploreBotsConnection": { "edges": edges } } })).into_response()
+}
@@ -0,0 +1,301 @@
+use crate::types::{ChatEventType, ChatMessage, ChatRequest, ChatResponse, ChatResponseData};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+#[cfg(feature = "trace")]
+use tracing::debug;
+
+// 每條消息的固定開銷（角色、分隔符等）
+const DEFAULT_MESSAGE_OVERHEAD: usize = 4;
+// 每個請求的固定開銷（回覆起始標記等）
+const DEFAULT_REQUEST_OVERHEAD: usize = 3;
+
+// 分詞器 trait，可替換為實際模型的分詞器
+pub trait Tokenizer: Send + Sync {
+    /// 計算文本的 token 數量
+    fn count_tokens(&self, text: &str) -> usize;
+}
+
+// 預設的啟發式分詞器：CJK 字符按字計算，拉丁文本按字符數估算
+#[derive(Debug, Clone, Copy)]
+pub struct HeuristicTokenizer {
+    pub latin_chars_per_token: f32,
+    pub cjk_tokens_per_char: f32,
+}
+
+impl Default for HeuristicTokenizer {
+    fn default() -> Self {
+        Self {
+            latin_chars_per_token: 4.0,
+            cjk_tokens_per_char: 1.0,
+        }
+    }
+}
+
+impl Tokenizer for HeuristicTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        let mut cjk_chars = 0usize;
+        let mut other_chars = 0usize;
+
+        for c in text.chars() {
+            if is_cjk(c) {
+                cjk_chars += 1;
+            } else {
+                other_chars += 1;
+            }
+        }
+
+        let cjk_tokens = (cjk_chars as f32 * self.cjk_tokens_per_char).ceil() as usize;
+        let other_tokens = (other_chars as f32 / self.latin_chars_per_token).ceil() as usize;
+        cjk_tokens + other_tokens
+    }
+}
+
+// 判斷字符是否屬於 CJK 範圍（含假名、諺文及全形標點）
+fn is_cjk(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x3000..=0x303F // CJK 標點
+            | 0x3040..=0x30FF // 平假名、片假名
+            | 0x3400..=0x4DBF // CJK 擴展 A
+            | 0x4E00..=0x9FFF // CJK 統一表意文字
+            | 0xAC00..=0xD7AF // 諺文音節
+            | 0xF900..=0xFAFF // CJK 相容表意文字
+            | 0xFF00..=0xFFEF // 全形字符
+            | 0x20000..=0x2FA1F // CJK 擴展 B 及以後
+    )
+}
+
+// 請求的 token 估算器
+#[derive(Clone)]
+pub struct TokenEstimator {
+    tokenizer: Arc<dyn Tokenizer>,
+    message_overhead: usize,
+    request_overhead: usize,
+}
+
+impl Default for TokenEstimator {
+    fn default() -> Self {
+        Self::new(HeuristicTokenizer::default())
+    }
+}
+
+impl TokenEstimator {
+    pub fn new(tokenizer: impl Tokenizer + 'static) -> Self {
+        Self {
+            tokenizer: Arc::new(tokenizer),
+            message_overhead: DEFAULT_MESSAGE_OVERHEAD,
+            request_overhead: DEFAULT_REQUEST_OVERHEAD,
+        }
+    }
+
+    /// 設置每條消息及每個請求的固定開銷
+    pub fn with_overhead(mut self, message_overhead: usize, request_overhead: usize) -> Self {
+        self.message_overhead = message_overhead;
+        self.request_overhead = request_overhead;
+        self
+    }
+
+    /// 計算文本的 token 數量
+    pub fn count_text(&self, text: &str) -> usize {
+        self.tokenizer.count_tokens(text)
+    }
+
+    /// 估算單條消息的 token 數量
+    pub fn estimate_message(&self, message: &ChatMessage) -> usize {
+        self.message_overhead
+            + self.tokenizer.count_tokens(message.role.as_str())
+            + self.tokenizer.count_tokens(&message.content)
+    }
+
+    /// 估算整個請求的輸入 token 數量（包含工具定義、工具調用及結果）
+    pub fn estimate_request(&self, request: &ChatRequest) -> usize {
+        let mut total = self.request_overhead;
+
+        for message in &request.query {
+            total += self.estimate_message(message);
+        }
+
+        if let Some(ref tools) = request.tools
+            && let Ok(json) = serde_json::to_string(tools)
+        {
+            total += self.tokenizer.count_tokens(&json);
+        }
+
+        if let Some(ref tool_calls) = request.tool_calls {
+            for tool_call in tool_calls {
+                total += self.message_overhead
+                    + self.tokenizer.count_tokens(&tool_call.function.name)
+                    + self.tokenizer.count_tokens(&tool_call.function.arguments);
+            }
+        }
+
+        if let Some(ref tool_results) = request.tool_results {
+            for tool_result in tool_results {
+                total += self.message_overhead
+                    + self.tokenizer.count_tokens(&tool_result.name)
+                    + self.tokenizer.count_tokens(&tool_result.content);
+            }
+        }
+
+        #[cfg(feature = "trace")]
+        debug!("估算請求輸入 token 數量: {}", total);
+
+        total
+    }
+}
+
+// 單個 bot 的點數計費方式
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct BotCost {
+    // 每條消息的固定點數
+    #[serde(default)]
+    pub per_message: f64,
+    // 每 1000 個輸入 token 的點數
+    #[serde(default)]
+    pub per_1k_input_tokens: f64,
+    // 每 1000 個輸出 token 的點數
+    #[serde(default)]
+    pub per_1k_output_tokens: f64,
+}
+
+impl BotCost {
+    /// 根據輸入及輸出 token 數量計算點數
+    pub fn points(&self, input_tokens: usize, output_tokens: usize) -> f64 {
+        self.per_message
+            + input_tokens as f64 / 1000.0 * self.per_1k_input_tokens
+            + output_tokens as f64 / 1000.0 * self.per_1k_output_tokens
+    }
+}
+
+// 各 bot 的點數價目表
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CostTable {
+    #[serde(default)]
+    pub bots: HashMap<String, BotCost>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<BotCost>,
+}
+
+impl CostTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 設置指定 bot 的計費方式
+    pub fn with_bot(mut self, bot_name: &str, cost: BotCost) -> Self {
+        self.bots.insert(bot_name.to_string(), cost);
+        self
+    }
+
+    /// 設置未列出 bot 時使用的預設計費方式
+    pub fn with_default(mut self, cost: BotCost) -> Self {
+        self.default = Some(cost);
+        self
+    }
+
+    /// 取得指定 bot 的計費方式（bot 名稱不區分大小寫）
+    pub fn get(&self, bot_name: &str) -> Option<&BotCost> {
+        self.bots
+            .get(bot_name)
+            .or_else(|| {
+                self.bots
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(bot_name))
+                    .map(|(_, cost)| cost)
+            })
+            .or(self.default.as_ref())
+    }
+
+    /// 估算請求的輸入大小及點數
+    pub fn estimate(
+        &self,
+        bot_name: &str,
+        request: &ChatRequest,
+        estimator: &TokenEstimator,
+    ) -> UsageEstimate {
+        let input_tokens = estimator.estimate_request(request);
+        let points = self.get(bot_name).map(|cost| cost.points(input_tokens, 0));
+
+        #[cfg(feature = "trace")]
+        debug!(
+            "估算 bot {} 的請求成本 | 輸入 token: {} | 點數: {:?}",
+            bot_name, input_tokens, points
+        );
+
+        UsageEstimate {
+            input_tokens,
+            output_tokens: 0,
+            points,
+        }
+    }
+}
+
+// 用量估算結果
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct UsageEstimate {
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub points: Option<f64>,
+}
+
+// 從串流 Text 事件中累積輸出大小
+#[derive(Clone)]
+pub struct UsageTracker {
+    estimator: TokenEstimator,
+    input_tokens: usize,
+    output_text: String,
+}
+
+impl UsageTracker {
+    pub fn new(estimator: TokenEstimator, request: &ChatRequest) -> Self {
+        Self {
+            input_tokens: estimator.estimate_request(request),
+            estimator,
+            output_text: String::new(),
+        }
+    }
+
+    /// 記錄一個串流事件，Text 會累加，ReplaceResponse 會覆蓋已累積的輸出
+    pub fn observe(&mut self, response: &ChatResponse) {
+        match (&response.event, &response.data) {
+            (ChatEventType::Text, Some(ChatResponseData::Text { text })) => {
+                self.output_text.push_str(text);
+            }
+            (ChatEventType::ReplaceResponse, Some(ChatResponseData::Text { text })) => {
+                self.output_text.clear();
+                self.output_text.push_str(text);
+            }
+            _ => {}
+        }
+    }
+
+    /// 已累積的輸出字符數
+    pub fn output_chars(&self) -> usize {
+        self.output_text.chars().count()
+    }
+
+    /// 已累積的輸出文本
+    pub fn output_text(&self) -> &str {
+        &self.output_text
+    }
+
+    /// 目前的輸入及輸出 token 估算
+    pub fn usage(&self) -> UsageEstimate {
+        UsageEstimate {
+            input_tokens: self.input_tokens,
+            output_tokens: self.estimator.count_text(&self.output_text),
+            points: None,
+        }
+    }
+
+    /// 根據價目表計算目前的點數估算
+    pub fn usage_with_cost(&self, bot_name: &str, table: &CostTable) -> UsageEstimate {
+        let mut usage = self.usage();
+        usage.points = table
+            .get(bot_name)
+            .map(|cost| cost.points(usage.input_tokens, usage.output_tokens));
+        usage
+    }
+}
@@ -1,6 +1,16 @@
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use crate::error::PoeError;
+use bytes::Bytes;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
 use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+// Poe 協議版本
+pub const POE_PROTOCOL_VERSION: &str = "1.1";
 
 // Bot Chat 請求結構
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -23,24 +33,454 @@ pub struct ChatRequest {
     pub logit_bias: Option<HashMap<String, f32>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stop_sequences: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_system_prompt: Option<bool>,
+    // 尚未建模的協議欄位
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 // 消息結構
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ChatMessage {
-    pub role: String,
+    pub role: Role,
     pub content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub attachments: Option<Vec<Attachment>>,
-    pub content_type: String,
+    pub content_type: ContentType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub feedback: Vec<MessageFeedback>,
+    // 尚未建模的協議欄位
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// 用戶對消息的回饋
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MessageFeedback {
+    pub r#type: FeedbackType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+// 回饋類型
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedbackType {
+    Like,
+    Dislike,
+}
+
+// 消息角色，未知角色以 Other 保留原始字串
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Role {
+    System,
+    #[default]
+    User,
+    Bot,
+    Tool,
+    Other(String),
+}
+
+impl Role {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Bot => "bot",
+            Role::Tool => "tool",
+            Role::Other(role) => role,
+        }
+    }
+}
+
+impl From<&str> for Role {
+    fn from(role: &str) -> Self {
+        match role {
+            "system" => Role::System,
+            "user" => Role::User,
+            // OpenAI 風格的 assistant 自動對應為 Poe 的 bot
+            "bot" | "assistant" => Role::Bot,
+            "tool" => Role::Tool,
+            other => Role::Other(other.to_string()),
+        }
+    }
+}
+
+impl From<String> for Role {
+    fn from(role: String) -> Self {
+        Role::from(role.as_str())
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for Role {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Role {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Serialize for Role {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Role {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Role::from(String::deserialize(deserializer)?))
+    }
+}
+
+// 消息內容類型，未知類型以 Other 保留原始字串
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum ContentType {
+    #[default]
+    Markdown,
+    Plain,
+    Other(String),
+}
+
+impl ContentType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ContentType::Markdown => "text/markdown",
+            ContentType::Plain => "text/plain",
+            ContentType::Other(content_type) => content_type,
+        }
+    }
+}
+
+impl From<&str> for ContentType {
+    fn from(content_type: &str) -> Self {
+        match content_type {
+            "text/markdown" => ContentType::Markdown,
+            "text/plain" => ContentType::Plain,
+            other => ContentType::Other(other.to_string()),
+        }
+    }
+}
+
+impl From<String> for ContentType {
+    fn from(content_type: String) -> Self {
+        ContentType::from(content_type.as_str())
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for ContentType {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for ContentType {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Serialize for ContentType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ContentType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(ContentType::from(String::deserialize(deserializer)?))
+    }
+}
+
+impl Default for ChatRequest {
+    fn default() -> Self {
+        Self {
+            version: POE_PROTOCOL_VERSION.to_string(),
+            r#type: "query".to_string(),
+            query: Vec::new(),
+            user_id: String::new(),
+            conversation_id: String::new(),
+            message_id: String::new(),
+            tools: None,
+            tool_calls: None,
+            tool_results: None,
+            temperature: None,
+            logit_bias: None,
+            stop_sequences: None,
+            metadata: None,
+            skip_system_prompt: None,
+            extra: Map::new(),
+        }
+    }
+}
+
+impl ChatRequest {
+    /// 建立請求構建器
+    pub fn builder() -> ChatRequestBuilder {
+        ChatRequestBuilder::default()
+    }
+
+    /// 建立轉發給其他 bot 的請求，保留身份欄位及對話內容，
+    /// 但不帶上屬於原 bot 的工具定義、工具調用及結果
+    pub fn forwarded(&self) -> ChatRequest {
+        ChatRequest {
+            version: self.version.clone(),
+            query: self.query.clone(),
+            user_id: self.user_id.clone(),
+            conversation_id: self.conversation_id.clone(),
+            message_id: self.message_id.clone(),
+            temperature: self.temperature,
+            logit_bias: self.logit_bias.clone(),
+            stop_sequences: self.stop_sequences.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// 在發送前驗證請求內容
+    pub fn validate(&self) -> Result<(), PoeError> {
+        let Some(last_message) = self.query.last() else {
+            return Err(PoeError::InvalidRequest("query 不能為空".to_string()));
+        };
+
+        for (index, message) in self.query.iter().enumerate() {
+            if let Role::Other(ref role) = message.role {
+                return Err(PoeError::InvalidRequest(format!(
+                    "第 {} 條消息的角色無效: {}",
+                    index, role
+                )));
+            }
+        }
+
+        let has_attachments = last_message
+            .attachments
+            .as_ref()
+            .is_some_and(|attachments| !attachments.is_empty());
+        if last_message.content.trim().is_empty() && !has_attachments {
+            return Err(PoeError::InvalidRequest(
+                "最後一條消息的內容不能為空".to_string(),
+            ));
+        }
+
+        if let Some(temperature) = self.temperature
+            && !(0.0..=2.0).contains(&temperature)
+        {
+            return Err(PoeError::InvalidRequest(format!(
+                "temperature 必須介於 0 與 2 之間: {}",
+                temperature
+            )));
+        }
+
+        if let Some(ref tool_results) = self.tool_results {
+            let tool_calls = self.tool_calls.as_deref().unwrap_or_default();
+            for tool_result in tool_results {
+                if !tool_calls
+                    .iter()
+                    .any(|call| call.id == tool_result.tool_call_id)
+                {
+                    return Err(PoeError::MissingToolCallId(
+                        tool_result.tool_call_id.clone(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// ChatRequest 構建器
+#[derive(Debug, Clone, Default)]
+pub struct ChatRequestBuilder {
+    request: ChatRequest,
+}
+
+impl ChatRequestBuilder {
+    /// 添加一條消息
+    pub fn message(mut self, message: ChatMessage) -> Self {
+        self.request.query.push(message);
+        self
+    }
+
+    /// 添加多條消息
+    pub fn messages(mut self, messages: impl IntoIterator<Item = ChatMessage>) -> Self {
+        self.request.query.extend(messages);
+        self
+    }
+
+    /// 添加一條系統消息
+    pub fn system(self, content: impl Into<String>) -> Self {
+        self.message(ChatMessage::system(content))
+    }
+
+    /// 添加一條用戶消息
+    pub fn user(self, content: impl Into<String>) -> Self {
+        self.message(ChatMessage::user(content))
+    }
+
+    /// 添加一條 bot 消息
+    pub fn bot(self, content: impl Into<String>) -> Self {
+        self.message(ChatMessage::bot(content))
+    }
+
+    pub fn user_id(mut self, user_id: impl Into<String>) -> Self {
+        self.request.user_id = user_id.into();
+        self
+    }
+
+    pub fn conversation_id(mut self, conversation_id: impl Into<String>) -> Self {
+        self.request.conversation_id = conversation_id.into();
+        self
+    }
+
+    pub fn message_id(mut self, message_id: impl Into<String>) -> Self {
+        self.request.message_id = message_id.into();
+        self
+    }
+
+    pub fn tools(mut self, tools: Vec<ChatTool>) -> Self {
+        self.request.tools = Some(tools);
+        self
+    }
+
+    pub fn tool_calls(mut self, tool_calls: Vec<ChatToolCall>) -> Self {
+        self.request.tool_calls = Some(tool_calls);
+        self
+    }
+
+    pub fn tool_results(mut self, tool_results: Vec<ChatToolResult>) -> Self {
+        self.request.tool_results = Some(tool_results);
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.request.temperature = Some(temperature);
+        self
+    }
+
+    pub fn logit_bias(mut self, logit_bias: HashMap<String, f32>) -> Self {
+        self.request.logit_bias = Some(logit_bias);
+        self
+    }
+
+    pub fn stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.request.stop_sequences = Some(stop_sequences);
+        self
+    }
+
+    pub fn metadata(mut self, metadata: impl Into<String>) -> Self {
+        self.request.metadata = Some(metadata.into());
+        self
+    }
+
+    pub fn skip_system_prompt(mut self, skip_system_prompt: bool) -> Self {
+        self.request.skip_system_prompt = Some(skip_system_prompt);
+        self
+    }
+
+    /// 設置尚未建模的協議欄位
+    pub fn extra(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.request.extra.insert(key.into(), value);
+        self
+    }
+
+    /// 構建並驗證請求
+    pub fn build(self) -> Result<ChatRequest, PoeError> {
+        self.request.validate()?;
+        Ok(self.request)
+    }
+
+    /// 構建請求但不進行驗證
+    pub fn build_unchecked(self) -> ChatRequest {
+        self.request
+    }
+}
+
+impl ChatMessage {
+    pub fn new(role: impl Into<Role>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    /// 建立系統消息
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    /// 建立用戶消息
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    /// 建立 bot 消息
+    pub fn bot(content: impl Into<String>) -> Self {
+        Self::new(Role::Bot, content)
+    }
+
+    /// 設置內容類型
+    pub fn with_content_type(mut self, content_type: impl Into<ContentType>) -> Self {
+        self.content_type = content_type.into();
+        self
+    }
+
+    /// 添加附件
+    pub fn with_attachment(mut self, attachment: Attachment) -> Self {
+        self.attachments
+            .get_or_insert_with(Vec::new)
+            .push(attachment);
+        self
+    }
 }
 
 // ChatMessage 的Attachment 結構
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Attachment {
     pub url: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parsed_content: Option<String>,
+    // 尚未建模的協議欄位
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl From<FileUploadResponse> for Attachment {
+    fn from(response: FileUploadResponse) -> Self {
+        Self {
+            url: response.attachment_url,
+            content_type: response.mime_type,
+            ..Default::default()
+        }
+    }
 }
 
 // 工具定義相關結構
@@ -101,6 +541,155 @@ pub struct PartialToolCall {
     pub function_arguments: String,
 }
 
+// settings 請求
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SettingsRequest {
+    pub version: String,
+    pub r#type: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// settings 回應，宣告 bot 的依賴及功能
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SettingsResponse {
+    #[serde(default = "default_settings_response_version")]
+    pub response_version: u32,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub server_bot_dependencies: HashMap<String, u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_attachments: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expand_text_attachments: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enable_image_comprehension: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub introduction_message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enable_multi_bot_chat_prompting: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_clear_window_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_user_context_clear: Option<bool>,
+    // 尚未建模的協議欄位
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn default_settings_response_version() -> u32 {
+    2
+}
+
+impl Default for SettingsResponse {
+    fn default() -> Self {
+        Self {
+            response_version: default_settings_response_version(),
+            server_bot_dependencies: HashMap::new(),
+            allow_attachments: None,
+            expand_text_attachments: None,
+            enable_image_comprehension: None,
+            introduction_message: None,
+            enable_multi_bot_chat_prompting: None,
+            context_clear_window_secs: None,
+            allow_user_context_clear: None,
+            extra: Map::new(),
+        }
+    }
+}
+
+impl SettingsResponse {
+    /// 宣告每條消息會調用指定 bot 的次數
+    pub fn with_dependency(mut self, bot_name: &str, calls_per_message: u32) -> Self {
+        self.server_bot_dependencies
+            .insert(bot_name.to_string(), calls_per_message);
+        self
+    }
+
+    pub fn with_allow_attachments(mut self, allow_attachments: bool) -> Self {
+        self.allow_attachments = Some(allow_attachments);
+        self
+    }
+
+    pub fn with_introduction_message(mut self, introduction_message: &str) -> Self {
+        self.introduction_message = Some(introduction_message.to_string());
+        self
+    }
+}
+
+// report_feedback 請求
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportFeedbackRequest {
+    pub version: String,
+    pub r#type: String,
+    pub message_id: String,
+    pub user_id: String,
+    pub conversation_id: String,
+    pub feedback_type: FeedbackType,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// report_error 請求
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportErrorRequest {
+    pub version: String,
+    pub r#type: String,
+    pub message: String,
+    #[serde(default)]
+    pub metadata: Value,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// 依 type 欄位區分的 Poe 請求
+#[derive(Debug, Serialize, Clone)]
+#[serde(untagged)]
+pub enum PoeRequest {
+    Query(ChatRequest),
+    Settings(SettingsRequest),
+    ReportFeedback(ReportFeedbackRequest),
+    ReportError(ReportErrorRequest),
+    Unknown(Value),
+}
+
+impl PoeRequest {
+    /// 根據 type 欄位解析請求，未知類型以 Unknown 保留
+    pub fn from_value(value: Value) -> Result<Self, PoeError> {
+        let request_type = value
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        Ok(match request_type {
+            "query" => PoeRequest::Query(serde_json::from_value(value)?),
+            "settings" => PoeRequest::Settings(serde_json::from_value(value)?),
+            "report_feedback" => PoeRequest::ReportFeedback(serde_json::from_value(value)?),
+            "report_error" => PoeRequest::ReportError(serde_json::from_value(value)?),
+            _ => PoeRequest::Unknown(value),
+        })
+    }
+
+    /// 請求的 type 欄位
+    pub fn request_type(&self) -> &str {
+        match self {
+            PoeRequest::Query(request) => &request.r#type,
+            PoeRequest::Settings(request) => &request.r#type,
+            PoeRequest::ReportFeedback(request) => &request.r#type,
+            PoeRequest::ReportError(request) => &request.r#type,
+            PoeRequest::Unknown(value) => value
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PoeRequest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        PoeRequest::from_value(value).map_err(serde::de::Error::custom)
+    }
+}
+
 // 事件響應
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatResponse {
@@ -118,10 +707,45 @@ pub enum ChatEventType {
     File,
     Done,
     Error,
+    Meta,
+    SuggestedReply,
+    Unknown,
+}
+
+// meta 事件數據
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MetaData {
+    #[serde(default)]
+    pub content_type: ContentType,
+    #[serde(default = "default_linkify")]
+    pub linkify: bool,
+    #[serde(default)]
+    pub suggested_replies: bool,
+    #[serde(default)]
+    pub refetch_settings: bool,
+    // 尚未建模的協議欄位
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn default_linkify() -> bool {
+    true
+}
+
+impl Default for MetaData {
+    fn default() -> Self {
+        Self {
+            content_type: ContentType::Markdown,
+            linkify: true,
+            suggested_replies: false,
+            refetch_settings: false,
+            extra: Map::new(),
+        }
+    }
 }
 
 // 檔案數據結構
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct FileData {
     pub url: String,
     pub name: String,
@@ -129,17 +753,91 @@ pub struct FileData {
     pub inline_ref: String,
 }
 
-// 響應資料的可能類型
-#[derive(Debug, Serialize, Deserialize)]
+// 響應資料的可能類型，序列化時不帶標籤
+#[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub enum ChatResponseData {
     Text { text: String },
     Error { text: String, allow_retry: bool },
     ToolCalls(Vec<ChatToolCall>),
     File(FileData),
+    Unknown { name: String, data: String },
+    Meta(MetaData),
     Empty,
 }
 
+// MetaData 的已知欄位，包含任一欄位的物件視為 meta 數據
+const META_FIELDS: [&str; 4] = [
+    "content_type",
+    "linkify",
+    "suggested_replies",
+    "refetch_settings",
+];
+
+impl ChatResponseData {
+    /// 根據欄位判斷數據類型
+    ///
+    /// MetaData 的欄位都有預設值，若按順序嘗試各類型，任何無法識別的物件都會被當作 Meta，
+    /// 因此改為按欄位明確判斷：空物件或包含任一已知 meta 欄位的物件視為 Meta，
+    /// 其餘未知欄位保留在 extra 中；無法識別的結構返回錯誤。
+    pub fn from_value(value: Value) -> Result<Self, PoeError> {
+        let object = match value {
+            Value::Null => return Ok(ChatResponseData::Empty),
+            Value::Array(_) => {
+                return Ok(ChatResponseData::ToolCalls(serde_json::from_value(value)?));
+            }
+            Value::Object(ref object) => object,
+            other => {
+                return Err(PoeError::EventParseFailed(format!(
+                    "無法識別的響應數據: {}",
+                    other
+                )));
+            }
+        };
+        let has = |field: &str| object.contains_key(field);
+
+        Ok(if has("text") && has("allow_retry") {
+            let text = object["text"].as_str();
+            let allow_retry = object["allow_retry"].as_bool();
+            match (text, allow_retry) {
+                (Some(text), Some(allow_retry)) => ChatResponseData::Error {
+                    text: text.to_string(),
+                    allow_retry,
+                },
+                _ => {
+                    return Err(PoeError::EventParseFailed(format!(
+                        "無效的錯誤數據: {}",
+                        value
+                    )));
+                }
+            }
+        } else if has("text") {
+            let text: String = serde_json::from_value(object["text"].clone())?;
+            ChatResponseData::Text { text }
+        } else if has("url") {
+            ChatResponseData::File(serde_json::from_value(value)?)
+        } else if has("name") && has("data") {
+            let name: String = serde_json::from_value(object["name"].clone())?;
+            let data: String = serde_json::from_value(object["data"].clone())?;
+            ChatResponseData::Unknown { name, data }
+        } else if object.is_empty() || META_FIELDS.iter().any(|field| has(field)) {
+            ChatResponseData::Meta(serde_json::from_value(value)?)
+        } else {
+            return Err(PoeError::EventParseFailed(format!(
+                "無法識別的響應數據: {}",
+                value
+            )));
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ChatResponseData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        ChatResponseData::from_value(value).map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ModelResponse {
     pub data: Vec<ModelInfo>,
@@ -165,10 +863,147 @@ pub enum FileUploadRequest {
     RemoteFile {
         download_url: String,
     },
+    // 記憶體中的內容，無法序列化
+    #[serde(skip)]
+    Bytes {
+        data: Bytes,
+        file_name: String,
+        mime_type: Option<String>,
+    },
+    // 任意非同步讀取來源，len 已知時用於大小檢查，無法序列化
+    #[serde(skip)]
+    Reader {
+        reader: UploadReader,
+        file_name: String,
+        mime_type: Option<String>,
+        len: Option<u64>,
+    },
+}
+
+impl FileUploadRequest {
+    /// 依來源建立上傳請求，http(s) 地址視為遠端檔案，其他視為本地路徑
+    pub fn from_source(source: &str) -> Self {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            Self::RemoteFile {
+                download_url: source.to_string(),
+            }
+        } else {
+            Self::LocalFile {
+                file: source.to_string(),
+                mime_type: None,
+            }
+        }
+    }
+
+    /// 用作附件名稱的檔名，遠端檔案取地址的最後一段路徑
+    pub fn file_name(&self) -> Option<String> {
+        match self {
+            Self::LocalFile { file, .. } => Path::new(file)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            Self::RemoteFile { download_url } => url::Url::parse(download_url)
+                .ok()?
+                .path_segments()?
+                .next_back()
+                .filter(|segment| !segment.is_empty())
+                .map(str::to_string),
+            Self::Bytes { file_name, .. } | Self::Reader { file_name, .. } => {
+                Some(file_name.clone())
+            }
+        }
+    }
+
+    /// 複製請求以便重試，讀取來源只能讀取一次，因此返回 None
+    pub fn try_clone(&self) -> Option<Self> {
+        match self {
+            Self::LocalFile { file, mime_type } => Some(Self::LocalFile {
+                file: file.clone(),
+                mime_type: mime_type.clone(),
+            }),
+            Self::RemoteFile { download_url } => Some(Self::RemoteFile {
+                download_url: download_url.clone(),
+            }),
+            Self::Bytes {
+                data,
+                file_name,
+                mime_type,
+            } => Some(Self::Bytes {
+                data: data.clone(),
+                file_name: file_name.clone(),
+                mime_type: mime_type.clone(),
+            }),
+            Self::Reader { .. } => None,
+        }
+    }
+}
+
+impl From<&str> for FileUploadRequest {
+    fn from(source: &str) -> Self {
+        Self::from_source(source)
+    }
+}
+
+impl From<String> for FileUploadRequest {
+    fn from(source: String) -> Self {
+        Self::from_source(&source)
+    }
+}
+
+impl From<&String> for FileUploadRequest {
+    fn from(source: &String) -> Self {
+        Self::from_source(source)
+    }
+}
+
+// 路徑一律視為本地檔案
+impl From<&Path> for FileUploadRequest {
+    fn from(path: &Path) -> Self {
+        Self::LocalFile {
+            file: path.to_string_lossy().into_owned(),
+            mime_type: None,
+        }
+    }
+}
+
+impl From<PathBuf> for FileUploadRequest {
+    fn from(path: PathBuf) -> Self {
+        Self::from(path.as_path())
+    }
+}
+
+impl From<&PathBuf> for FileUploadRequest {
+    fn from(path: &PathBuf) -> Self {
+        Self::from(path.as_path())
+    }
+}
+
+// 用於上傳的非同步讀取來源
+pub struct UploadReader(Pin<Box<dyn AsyncRead + Send>>);
+
+impl UploadReader {
+    pub fn new(reader: impl AsyncRead + Send + 'static) -> Self {
+        Self(Box::pin(reader))
+    }
+}
+
+impl fmt::Debug for UploadReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("UploadReader")
+    }
+}
+
+impl AsyncRead for UploadReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.0.as_mut().poll_read(cx, buf)
+    }
 }
 
 // 文件上傳響應結構
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileUploadResponse {
     pub attachment_url: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -0,0 +1,419 @@
+use crate::error::PoeError;
+use crate::mime::mime_matches;
+use crate::types::{FileUploadRequest, FileUploadResponse};
+use bytes::Bytes;
+use futures_util::Stream;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+pub use tokio_util::sync::CancellationToken;
+#[cfg(feature = "trace")]
+use tracing::warn;
+
+// 上傳前的檢查規則，類型模式支援 image/* 形式的通配
+#[derive(Debug, Clone, Default)]
+pub struct UploadPolicy {
+    // 允許的 MIME 類型，None 表示不限制
+    pub allowed_types: Option<Vec<String>>,
+    // 禁止的 MIME 類型，優先於允許列表
+    pub denied_types: Vec<String>,
+    // 單個檔案的大小上限（字節），None 表示不限制
+    pub max_file_size: Option<u64>,
+}
+
+impl UploadPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 加入允許的 MIME 類型，設置後只允許列表中的類型
+    pub fn allow(mut self, mime_type: &str) -> Self {
+        self.allowed_types
+            .get_or_insert_with(Vec::new)
+            .push(mime_type.to_string());
+        self
+    }
+
+    /// 加入禁止的 MIME 類型
+    pub fn deny(mut self, mime_type: &str) -> Self {
+        self.denied_types.push(mime_type.to_string());
+        self
+    }
+
+    /// 設置單個檔案的大小上限（字節）
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    /// 檢查檔案大小是否超過上限
+    pub fn check_file_size(&self, name: &str, size: u64) -> Result<(), PoeError> {
+        match self.max_file_size {
+            Some(max_file_size) if size > max_file_size => {
+                #[cfg(feature = "trace")]
+                warn!(
+                    "檔案過大: {} | {} 字節，上限 {} 字節",
+                    name, size, max_file_size
+                );
+                Err(PoeError::FileTooLarge(format!(
+                    "{} 大小為 {} 字節，超過上限 {} 字節",
+                    name, size, max_file_size
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 檢查 MIME 類型是否允許上傳
+    pub fn check_mime_type(&self, mime_type: &str) -> Result<(), PoeError> {
+        let denied = self
+            .denied_types
+            .iter()
+            .any(|pattern| mime_matches(pattern, mime_type));
+        let allowed = self.allowed_types.as_ref().is_none_or(|allowed| {
+            allowed
+                .iter()
+                .any(|pattern| mime_matches(pattern, mime_type))
+        });
+
+        if denied || !allowed {
+            #[cfg(feature = "trace")]
+            warn!("上傳策略拒絕檔案類型: {}", mime_type);
+            return Err(PoeError::UnsupportedFileType(mime_type.to_string()));
+        }
+        Ok(())
+    }
+}
+
+// 上傳進度，total 在長度未知時為 None
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UploadProgress {
+    pub sent: u64,
+    pub total: Option<u64>,
+}
+
+impl UploadProgress {
+    /// 已完成的比例（0.0 至 1.0），長度未知時返回 None
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.0),
+            Some(total) => Some(self.sent as f64 / total as f64),
+            None => None,
+        }
+    }
+}
+
+type ProgressCallback = Arc<dyn Fn(UploadProgress) + Send + Sync>;
+
+// 批量上傳的預設並發數
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+
+// 重試前的預設等待時間，之後每次加倍
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// 上傳選項：進度回調、取消控制，以及批量上傳的並發數和重試次數
+#[derive(Clone)]
+pub struct UploadOptions {
+    progress: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
+    concurrency: usize,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            progress: None,
+            cancellation: None,
+            concurrency: DEFAULT_BATCH_CONCURRENCY,
+            retries: 0,
+            retry_delay: DEFAULT_RETRY_DELAY,
+        }
+    }
+}
+
+impl fmt::Debug for UploadOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UploadOptions")
+            .field("progress", &self.progress.is_some())
+            .field("cancellation", &self.cancellation)
+            .field("concurrency", &self.concurrency)
+            .field("retries", &self.retries)
+            .field("retry_delay", &self.retry_delay)
+            .finish()
+    }
+}
+
+impl UploadOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 設置批量上傳同時進行的最大數量（至少為 1）
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// 設置批量上傳中每個檔案失敗後的重試次數，只重試網絡錯誤及伺服器拒絕
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// 設置首次重試前的等待時間
+    pub fn with_retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    // 第 attempt 次重試前的等待時間
+    pub(crate) fn retry_delay(&self, attempt: u32) -> Duration {
+        self.retry_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+
+    /// 設置進度回調，每送出一塊內容調用一次；批量上傳時回報所有檔案的總進度
+    pub fn on_progress(
+        mut self,
+        callback: impl Fn(UploadProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// 設置取消控制，取消後進行中的上傳會中斷並返回 UploadCancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub fn cancellation(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    pub(crate) fn report(&self, progress: UploadProgress) {
+        if let Some(callback) = &self.progress {
+            callback(progress);
+        }
+    }
+
+    pub(crate) fn has_progress(&self) -> bool {
+        self.progress.is_some()
+    }
+
+    /// 已取消時返回錯誤
+    pub(crate) fn check_cancelled(&self) -> Result<(), PoeError> {
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(PoeError::UploadCancelled);
+        }
+        Ok(())
+    }
+
+    /// 執行上傳，取消時立即中斷並返回 UploadCancelled
+    pub(crate) async fn run<T>(
+        &self,
+        upload: impl Future<Output = Result<T, PoeError>>,
+    ) -> Result<T, PoeError> {
+        self.check_cancelled()?;
+        match &self.cancellation {
+            Some(token) => tokio::select! {
+                result = upload => result,
+                _ = token.cancelled() => {
+                    #[cfg(feature = "trace")]
+                    warn!("上傳已取消");
+                    Err(PoeError::UploadCancelled)
+                }
+            },
+            None => upload.await,
+        }
+    }
+
+    // 批量上傳時，將各檔案的進度合併為總進度後回報
+    pub(crate) fn for_batch_item(&self, index: usize, totals: &Arc<BatchProgress>) -> Self {
+        let Some(callback) = self.progress.clone() else {
+            return self.clone();
+        };
+        let totals = Arc::clone(totals);
+        Self {
+            progress: Some(Arc::new(move |progress: UploadProgress| {
+                callback(totals.update(index, progress));
+            })),
+            ..self.clone()
+        }
+    }
+}
+
+/// 批量上傳中單個檔案的結果，按輸入順序排列
+///
+/// request 為原始請求，可用於重新上傳失敗的項目；讀取來源只能讀取一次，此時為 None。
+#[derive(Debug)]
+pub struct BatchUploadItem {
+    pub request: Option<FileUploadRequest>,
+    pub result: Result<FileUploadResponse, PoeError>,
+}
+
+impl BatchUploadItem {
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    pub fn into_result(self) -> Result<FileUploadResponse, PoeError> {
+        self.result
+    }
+}
+
+// 網絡錯誤及伺服器拒絕可能是暫時性的，其他錯誤（檔案不存在、類型不允許等）重試也不會成功
+pub(crate) fn is_retryable(error: &PoeError) -> bool {
+    matches!(
+        error,
+        PoeError::RequestFailed(_) | PoeError::FileUploadFailed(_)
+    )
+}
+
+// 批量上傳中各檔案的進度
+pub(crate) struct BatchProgress {
+    items: std::sync::Mutex<Vec<UploadProgress>>,
+}
+
+impl BatchProgress {
+    pub(crate) fn new(totals: Vec<Option<u64>>) -> Self {
+        let items = totals
+            .into_iter()
+            .map(|total| UploadProgress { sent: 0, total })
+            .collect();
+        Self {
+            items: std::sync::Mutex::new(items),
+        }
+    }
+
+    fn update(&self, index: usize, progress: UploadProgress) -> UploadProgress {
+        let mut items = self.items.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(item) = items.get_mut(index) {
+            *item = progress;
+        }
+        UploadProgress {
+            sent: items.iter().map(|item| item.sent).sum(),
+            total: items.iter().map(|item| item.total).sum(),
+        }
+    }
+}
+
+// 上傳內容的串流包裝：統計實際送出的位元組、回報進度，並檢查與宣告的長度是否一致，
+// 以及實際大小是否超過上傳規則的上限（長度未知或宣告有誤時也能限制）
+//
+// 串流只能返回 io::Error，因此出錯時同時把對應的 PoeError 記錄在 error 中，
+// 請求失敗後由調用者取出，以返回準確的錯誤類型。
+pub(crate) struct CountingStream<S> {
+    inner: S,
+    file_name: String,
+    sent: u64,
+    len: Option<u64>,
+    policy: UploadPolicy,
+    options: UploadOptions,
+    error: Arc<Mutex<Option<PoeError>>>,
+    finished: bool,
+}
+
+impl<S> CountingStream<S> {
+    pub(crate) fn new(
+        inner: S,
+        file_name: &str,
+        len: Option<u64>,
+        policy: &UploadPolicy,
+        options: &UploadOptions,
+    ) -> Self {
+        Self {
+            inner,
+            file_name: file_name.to_string(),
+            sent: 0,
+            len,
+            policy: policy.clone(),
+            options: options.clone(),
+            error: Arc::new(Mutex::new(None)),
+            finished: false,
+        }
+    }
+
+    /// 串流出錯時記錄的錯誤
+    pub(crate) fn error_slot(&self) -> Arc<Mutex<Option<PoeError>>> {
+        Arc::clone(&self.error)
+    }
+
+    fn fail(&mut self, error: PoeError) -> Poll<Option<std::io::Result<Bytes>>> {
+        #[cfg(feature = "trace")]
+        warn!("上傳內容檢查失敗: {}", error);
+        let io_error = std::io::Error::other(error.to_string());
+        *self.error.lock().unwrap_or_else(|e| e.into_inner()) = Some(error);
+        self.finished = true;
+        Poll::Ready(Some(Err(io_error)))
+    }
+
+    fn length_mismatch(&mut self, len: u64) -> Poll<Option<std::io::Result<Bytes>>> {
+        let error = PoeError::FileUploadFailed(format!(
+            "{} 的實際長度與宣告的 {} 字節不符（已讀取 {} 字節）",
+            self.file_name, len, self.sent
+        ));
+        self.fail(error)
+    }
+}
+
+impl<S> Stream for CountingStream<S>
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Unpin,
+{
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        match Pin::new(&mut self.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                self.sent += chunk.len() as u64;
+                if let Err(error) = self.policy.check_file_size(&self.file_name, self.sent) {
+                    return self.fail(error);
+                }
+                if let Some(len) = self.len
+                    && self.sent > len
+                {
+                    return self.length_mismatch(len);
+                }
+                self.options.report(UploadProgress {
+                    sent: self.sent,
+                    total: self.len,
+                });
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                let io_error = std::io::Error::new(e.kind(), e.to_string());
+                self.fail(PoeError::FileReadError(io_error))
+            }
+            Poll::Ready(None) => {
+                if let Some(len) = self.len
+                    && self.sent != len
+                {
+                    return self.length_mismatch(len);
+                }
+                self.finished = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
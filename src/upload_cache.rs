@@ -0,0 +1,229 @@
+use crate::error::PoeError;
+use crate::types::FileUploadResponse;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
+#[cfg(feature = "trace")]
+use tracing::{debug, warn};
+
+// 計算檔案雜湊時每次讀取的長度
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+// 快取中的一筆上傳記錄
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedUpload {
+    pub response: FileUploadResponse,
+    // 寫入時間（Unix 毫秒數），用於判斷是否過期
+    pub created_at: u64,
+}
+
+/// 上傳快取的存儲後端
+///
+/// 快取只用於減少重複上傳，實現應自行處理讀寫錯誤而不影響上傳本身。
+pub trait UploadCacheStore: Send + Sync {
+    fn get(&self, key: &str) -> Option<CachedUpload>;
+    fn insert(&self, key: &str, entry: CachedUpload);
+    fn remove(&self, key: &str);
+}
+
+// 記憶體存儲，進程結束後失效
+#[derive(Debug, Default)]
+pub struct MemoryUploadCacheStore {
+    entries: Mutex<HashMap<String, CachedUpload>>,
+}
+
+impl MemoryUploadCacheStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedUpload>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl UploadCacheStore for MemoryUploadCacheStore {
+    fn get(&self, key: &str) -> Option<CachedUpload> {
+        self.entries().get(key).cloned()
+    }
+
+    fn insert(&self, key: &str, entry: CachedUpload) {
+        self.entries().insert(key.to_string(), entry);
+    }
+
+    fn remove(&self, key: &str) {
+        self.entries().remove(key);
+    }
+}
+
+// 檔案存儲，每筆記錄保存為目錄下的一個 JSON 檔案，可在多個進程間共用
+#[derive(Debug, Clone)]
+pub struct FileUploadCacheStore {
+    dir: PathBuf,
+}
+
+impl FileUploadCacheStore {
+    /// 使用指定目錄保存快取，目錄不存在時會自動建立
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, PoeError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // 鍵可能包含 URL，因此以其雜湊作為檔名
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}.json", hash_bytes(key.as_bytes())))
+    }
+}
+
+impl UploadCacheStore for FileUploadCacheStore {
+    fn get(&self, key: &str) -> Option<CachedUpload> {
+        let content = std::fs::read(self.entry_path(key)).ok()?;
+        serde_json::from_slice(&content)
+            .inspect_err(|_e| {
+                #[cfg(feature = "trace")]
+                warn!("上傳快取記錄損壞，忽略: {} | {}", key, _e);
+            })
+            .ok()
+    }
+
+    fn insert(&self, key: &str, entry: CachedUpload) {
+        let path = self.entry_path(key);
+        // 先寫入臨時檔案再改名，避免其他進程讀到不完整的內容
+        let temp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        let result = serde_json::to_vec(&entry)
+            .map_err(std::io::Error::other)
+            .and_then(|content| std::fs::write(&temp_path, content))
+            .and_then(|()| std::fs::rename(&temp_path, &path));
+        if let Err(_e) = result {
+            #[cfg(feature = "trace")]
+            warn!("寫入上傳快取失敗: {} | {}", path.display(), _e);
+            let _ = std::fs::remove_file(&temp_path);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        let _ = std::fs::remove_file(self.entry_path(key));
+    }
+}
+
+/// 上傳快取，以內容的 SHA-256 或遠端檔案的 URL 對應先前返回的上傳結果
+#[derive(Clone)]
+pub struct UploadCache {
+    store: Arc<dyn UploadCacheStore>,
+    ttl: Option<Duration>,
+}
+
+impl Default for UploadCache {
+    fn default() -> Self {
+        Self::with_store(MemoryUploadCacheStore::new())
+    }
+}
+
+impl fmt::Debug for UploadCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UploadCache")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UploadCache {
+    /// 使用記憶體存儲的快取，記錄不會過期
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_store(store: impl UploadCacheStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            ttl: None,
+        }
+    }
+
+    /// 設置記錄的有效期，過期的記錄會在查詢時刪除
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// 內容的快取鍵
+    pub fn content_key(data: &[u8]) -> String {
+        format!("sha256:{}", hash_bytes(data))
+    }
+
+    /// 遠端檔案的快取鍵
+    pub fn url_key(url: &str) -> String {
+        format!("url:{}", url)
+    }
+
+    /// 本地檔案的快取鍵，以串流方式讀取檔案計算雜湊
+    pub async fn file_key(path: &Path) -> Result<String, PoeError> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(format!("sha256:{}", to_hex(&hasher.finalize())))
+    }
+
+    /// 查詢未過期的上傳結果
+    pub fn get(&self, key: &str) -> Option<FileUploadResponse> {
+        let entry = self.store.get(key)?;
+        if let Some(ttl) = self.ttl
+            && unix_millis().saturating_sub(entry.created_at) >= ttl.as_millis() as u64
+        {
+            #[cfg(feature = "trace")]
+            debug!("上傳快取記錄已過期: {}", key);
+            self.store.remove(key);
+            return None;
+        }
+        #[cfg(feature = "trace")]
+        debug!("上傳快取命中: {} -> {}", key, entry.response.attachment_url);
+        Some(entry.response)
+    }
+
+    pub fn insert(&self, key: &str, response: &FileUploadResponse) {
+        self.store.insert(
+            key,
+            CachedUpload {
+                response: response.clone(),
+                created_at: unix_millis(),
+            },
+        );
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.store.remove(key);
+    }
+}
+
+fn hash_bytes(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
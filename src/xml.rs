@@ -1,5 +1,7 @@
+#[cfg(feature = "trace")]
+use crate::sse::truncate_str;
 use crate::types::{
-    ChatMessage, ChatRequest, ChatTool, ChatToolCall, ChatToolResult, FunctionCall,
+    ChatMessage, ChatRequest, ChatTool, ChatToolCall, ChatToolResult, FunctionCall, Role,
 };
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -12,21 +14,6 @@ fn get_next_call_id() -> u64 {
     GLOBAL_CALL_ID.fetch_add(1, Ordering::SeqCst)
 }
 
-#[cfg(feature = "trace")]
-fn safe_string_truncate(s: &str, max_bytes: usize) -> &str {
-    if s.len() <= max_bytes {
-        return s;
-    }
-
-    // 從 max_bytes 位置向前查找，直到找到有效的字符邊界
-    let mut end = max_bytes;
-    while end > 0 && !s.is_char_boundary(end) {
-        end -= 1;
-    }
-
-    &s[..end]
-}
-
 // XML 工具格式相關結構
 #[derive(Debug, Clone)]
 pub struct XmlTool {
@@ -212,7 +199,7 @@ impl ChatRequest {
             if !tools.is_empty() {
                 // 找到最後一條用戶消息
                 for message in self.query.iter_mut().rev() {
-                    if message.role == "user" {
+                    if message.role == Role::User {
                         // 添加完整的工具使用提示詞
                         let tool_usage_prompt = r#"
 
@@ -281,7 +268,7 @@ Now, begin your work based on the user's next prompt. Remember, you are a proble
             if !tool_results.is_empty() {
                 // 找到最後一條用戶消息
                 for message in self.query.iter_mut().rev() {
-                    if message.role == "user" {
+                    if message.role == Role::User {
                         // 添加工具結果分析提示詞
                         let tool_results_prompt = r#"
 
@@ -472,7 +459,7 @@ impl XmlToolCallParser {
         {
             use tracing::debug;
             debug!("嘗試解析單個工具調用，內容長度: {}", xml_content.len());
-            debug!("XML 內容預覽: {}", safe_string_truncate(xml_content, 200));
+            debug!("XML 內容預覽: {}", truncate_str(xml_content, 200));
         }
 
         // 首先嘗試解析 <invoke name="tool_name"> 格式
@@ -564,8 +551,9 @@ impl XmlToolCallParser {
 
         if let Some(start_pos) = xml_content.find(start_marker) {
             let content_start = start_pos + start_marker.len();
-            if let Some(end_pos) = xml_content.find(end_marker) {
-                let inner_content = &xml_content[content_start..end_pos];
+            // 只在開始標籤之後查找結束標籤，避免切片範圍反轉
+            if let Some(end_offset) = xml_content[content_start..].find(end_marker) {
+                let inner_content = &xml_content[content_start..content_start + end_offset];
 
                 // 查找第一個非空白字符後的 < 標籤
                 let trimmed = inner_content.trim();